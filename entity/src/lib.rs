@@ -7,5 +7,6 @@ pub mod user;
 pub mod group;
 pub mod user_group_rel;
 
+pub mod attachment;
 pub mod friend_request;
 pub mod read_index;
\ No newline at end of file
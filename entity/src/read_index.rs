@@ -13,6 +13,7 @@ pub struct Model {
     pub mid: Option<i64>,
     pub latest_mid: i64,
     pub uid_of_latest_msg: i32,
+    pub version: i64,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -18,6 +18,8 @@ pub struct Model {
     pub status: UserStatus,
     pub dgraph_uid: String,
     pub role: Role,
+    pub last_seen: Option<DateTime>,
+    pub hide_last_seen: bool,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS "attachment"
+(
+    id          integer                            not null
+        constraint attachment_pk
+            primary key autoincrement,
+    hash        varchar(64)                        not null,
+    name        varchar(255)                       not null,
+    mime        varchar(100)                       not null,
+    size        integer                            not null,
+    uploader    integer                            not null
+        constraint attachment_user_id_fk
+            references user,
+    create_time datetime default CURRENT_TIMESTAMP not null
+);
+
+create index if not exists attachment_hash_index
+    on attachment (hash);
@@ -0,0 +1,5 @@
+alter table attachment
+    add width integer;
+
+alter table attachment
+    add height integer;
@@ -1,5 +1,9 @@
 pub use sea_orm_migration::prelude::*;
 
+mod add_attachment_image;
+mod add_read_index_version;
+mod add_user_presence;
+mod create_attachment;
 mod create_table;
 
 pub struct Migrator;
@@ -9,6 +13,10 @@ impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
         vec![
             Box::new(create_table::Migration),
+            Box::new(create_attachment::Migration),
+            Box::new(add_attachment_image::Migration),
+            Box::new(add_user_presence::Migration),
+            Box::new(add_read_index_version::Migration),
         ]
     }
 }
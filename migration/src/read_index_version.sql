@@ -0,0 +1,5 @@
+alter table read_index
+    add version integer default 0 not null;
+
+create index read_index_uid_version_index
+    on read_index (uid, version);
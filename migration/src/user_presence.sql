@@ -0,0 +1,5 @@
+alter table user
+    add last_seen datetime;
+
+alter table user
+    add hide_last_seen boolean default false not null;
@@ -0,0 +1,46 @@
+use crate::{Error, Result};
+
+/// 会话，单聊由双方用户id确定（与顺序无关），群聊由群id确定
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Conversation {
+    Dm(i64, i64),
+    Group(i64),
+}
+
+const DM: u8 = 0;
+const GROUP: u8 = 1;
+
+impl Conversation {
+    pub fn dm(from_uid: i64, to_uid: i64) -> Self {
+        Conversation::Dm(from_uid.min(to_uid), from_uid.max(to_uid))
+    }
+
+    pub(crate) fn to_bytes(self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(17);
+        match self {
+            Conversation::Dm(a, b) => {
+                data.push(DM);
+                data.extend_from_slice(&a.min(b).to_be_bytes());
+                data.extend_from_slice(&a.max(b).to_be_bytes());
+            }
+            Conversation::Group(gid) => {
+                data.push(GROUP);
+                data.extend_from_slice(&gid.to_be_bytes());
+            }
+        }
+        data
+    }
+
+    pub(crate) fn from_bytes(data: &[u8]) -> Result<Self> {
+        match data {
+            [DM, rest @ ..] if rest.len() == 16 => Ok(Conversation::Dm(
+                i64::from_be_bytes(rest[0..8].try_into().unwrap()),
+                i64::from_be_bytes(rest[8..16].try_into().unwrap()),
+            )),
+            [GROUP, rest @ ..] if rest.len() == 8 => Ok(Conversation::Group(i64::from_be_bytes(
+                rest.try_into().unwrap(),
+            ))),
+            _ => Err(Error::InvalidData),
+        }
+    }
+}
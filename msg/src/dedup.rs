@@ -0,0 +1,182 @@
+use sled::{Batch, CompareAndSwapError};
+
+use crate::{Error, Messages, Result};
+
+/// 发送者的客户端去重键的状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientKey {
+    /// 去重键此前未使用或已过期，本次占用成功
+    Reserved,
+    /// 使用该去重键的消息正在发送
+    Pending,
+    /// 使用该去重键的消息已发送，值为消息id
+    Sent(i64),
+}
+
+impl<'a> Messages<'a> {
+    /// 占用发送者的去重键，reserved_at早于expire_before的去重键视为过期，
+    /// 早于lease_before仍未记录消息id的去重键视为发送已中断，可以重新占用
+    pub fn reserve_client_key(
+        &self,
+        uid: i64,
+        key: &str,
+        reserved_at: i64,
+        expire_before: i64,
+        lease_before: i64,
+    ) -> Result<ClientKey> {
+        self.remove_expired_client_keys(uid, expire_before)?;
+        let mut expected = None;
+        loop {
+            let result = self.db.db.compare_and_swap(
+                key_client(uid, key),
+                expected.as_ref().map(|reserved: &[u8; 8]| &reserved[..]),
+                Some(&reserved_at.to_be_bytes()[..]),
+            )?;
+            let current = match result {
+                Ok(_) => break,
+                Err(CompareAndSwapError { current, .. }) => current.ok_or(Error::InvalidData)?,
+            };
+            match current.len() {
+                8 => {
+                    let pending: [u8; 8] = current.as_ref().try_into().unwrap();
+                    if i64::from_be_bytes(pending) >= lease_before {
+                        return Ok(ClientKey::Pending);
+                    }
+                    expected = Some(pending);
+                }
+                16 => {
+                    return Ok(ClientKey::Sent(i64::from_be_bytes(
+                        current[8..16].try_into().unwrap(),
+                    )))
+                }
+                _ => return Err(Error::InvalidData),
+            }
+        }
+        let mut batch = Batch::default();
+        if let Some(pending) = expected {
+            batch.remove(key_client_queue(uid, i64::from_be_bytes(pending), key));
+        }
+        batch.insert(key_client_queue(uid, reserved_at, key), []);
+        self.db.db.apply_batch(batch)?;
+        Ok(ClientKey::Reserved)
+    }
+
+    /// 记录去重键对应的消息id
+    pub fn set_client_key_mid(&self, uid: i64, key: &str, mid: i64) -> Result<()> {
+        self.db.db.update_and_fetch(key_client(uid, key), |data| {
+            let mut data = data?.get(0..8)?.to_vec();
+            data.extend_from_slice(&mid.to_be_bytes());
+            Some(data)
+        })?;
+        Ok(())
+    }
+
+    /// 释放未发送成功的去重键，之后可以用该键重新发送
+    pub fn release_client_key(&self, uid: i64, key: &str) -> Result<()> {
+        if let Some(data) = self.db.db.remove(key_client(uid, key))? {
+            let reserved_at = i64::from_be_bytes(
+                data.get(0..8)
+                    .ok_or(Error::InvalidData)?
+                    .try_into()
+                    .unwrap(),
+            );
+            self.db.db.remove(key_client_queue(uid, reserved_at, key))?;
+        }
+        Ok(())
+    }
+
+    fn remove_expired_client_keys(&self, uid: i64, expire_before: i64) -> Result<()> {
+        let prefix = key_client_queue_prefix(uid);
+        let mut end = prefix.to_vec();
+        end.extend_from_slice(&((expire_before as u64) ^ (1 << 63)).to_be_bytes());
+        let mut batch = Batch::default();
+        for item in self.db.db.range(prefix.to_vec()..end) {
+            let (queue_key, _) = item?;
+            let key = std::str::from_utf8(&queue_key[20..]).map_err(|_| Error::InvalidData)?;
+            batch.remove(key_client(uid, key));
+            batch.remove(queue_key);
+        }
+        self.db.db.apply_batch(batch)?;
+        Ok(())
+    }
+}
+
+fn key_client(uid: i64, key: &str) -> Vec<u8> {
+    let mut data = b"CKEY/".to_vec();
+    data.extend_from_slice(&uid.to_be_bytes());
+    data.extend_from_slice(key.as_bytes());
+    data
+}
+
+fn key_client_queue_prefix(uid: i64) -> [u8; 12] {
+    let mut data = [0; 12];
+    data[0..4].copy_from_slice(b"CKQ/");
+    data[4..12].copy_from_slice(&uid.to_be_bytes());
+    data
+}
+
+/// 发送者的去重键按占用时间排序，用于清理过期的去重键
+fn key_client_queue(uid: i64, reserved_at: i64, key: &str) -> Vec<u8> {
+    let mut data = key_client_queue_prefix(uid).to_vec();
+    data.extend_from_slice(&((reserved_at as u64) ^ (1 << 63)).to_be_bytes());
+    data.extend_from_slice(key.as_bytes());
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::open_temp_db;
+    use crate::ClientKey;
+
+    #[test]
+    fn client_keys_dedup_until_expired() {
+        let (_dir, db) = open_temp_db();
+        let messages = db.messages();
+        assert_eq!(
+            messages.reserve_client_key(1, "a", 1000, 0, 0).unwrap(),
+            ClientKey::Reserved
+        );
+        assert_eq!(
+            messages.reserve_client_key(1, "a", 1100, 0, 0).unwrap(),
+            ClientKey::Pending
+        );
+        assert_eq!(
+            messages.reserve_client_key(2, "a", 1100, 0, 0).unwrap(),
+            ClientKey::Reserved
+        );
+        messages.set_client_key_mid(1, "a", 42).unwrap();
+        assert_eq!(
+            messages.reserve_client_key(1, "a", 1200, 0, 0).unwrap(),
+            ClientKey::Sent(42)
+        );
+
+        messages.release_client_key(2, "a").unwrap();
+        assert_eq!(
+            messages.reserve_client_key(2, "a", 1300, 0, 0).unwrap(),
+            ClientKey::Reserved
+        );
+
+        assert_eq!(
+            messages.reserve_client_key(1, "a", 5000, 2000, 0).unwrap(),
+            ClientKey::Reserved
+        );
+
+        // 发送中断的去重键在租期过后可以重新占用
+        assert_eq!(
+            messages.reserve_client_key(3, "b", 6000, 0, 0).unwrap(),
+            ClientKey::Reserved
+        );
+        assert_eq!(
+            messages.reserve_client_key(3, "b", 6100, 0, 6000).unwrap(),
+            ClientKey::Pending
+        );
+        assert_eq!(
+            messages.reserve_client_key(3, "b", 9000, 0, 8000).unwrap(),
+            ClientKey::Reserved
+        );
+        assert_eq!(
+            messages.reserve_client_key(3, "b", 9100, 0, 9000).unwrap(),
+            ClientKey::Pending
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use sled::Batch;
+
+use crate::hide::key_hidden;
+use crate::mention::key_mention;
+use crate::messages::{
+    key_attachment_ref, key_dm_msg, key_group_msg, key_merged_msg, key_msg, key_revision_prefix,
+    key_user_msg,
+};
+use crate::receipt::{key_delivered, key_read};
+use crate::thread::{key_thread_msg, key_thread_stat, key_thread_subscriber_prefix};
+use crate::{Conversation, Error, Messages, Result};
+
+/// 会到期的消息，记录删除时需要清理的所有位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expiry {
+    pub mid: i64,
+    /// 到期时间，毫秒时间戳
+    pub expire_at: i64,
+    pub conversation: Conversation,
+    /// 话题回复所属的根消息id
+    pub thread: Option<i64>,
+    /// 收到该消息的用户
+    pub uids: Vec<i64>,
+}
+
+impl Expiry {
+    fn to_bytes(&self) -> Vec<u8> {
+        let conversation = self.conversation.to_bytes();
+        let mut data = Vec::with_capacity(10 + conversation.len() + 8 * self.uids.len());
+        data.push(conversation.len() as u8);
+        data.extend_from_slice(&conversation);
+        match self.thread {
+            Some(root) => {
+                data.push(1);
+                data.extend_from_slice(&root.to_be_bytes());
+            }
+            None => data.push(0),
+        }
+        for uid in &self.uids {
+            data.extend_from_slice(&uid.to_be_bytes());
+        }
+        data
+    }
+
+    fn from_bytes(mid: i64, expire_at: i64, data: &[u8]) -> Result<Self> {
+        let (&len, data) = data.split_first().ok_or(Error::InvalidData)?;
+        if data.len() < len as usize + 1 {
+            return Err(Error::InvalidData);
+        }
+        let (conversation, data) = data.split_at(len as usize);
+        let conversation = Conversation::from_bytes(conversation)?;
+        let (thread, data) = match data {
+            [0, rest @ ..] => (None, rest),
+            [1, rest @ ..] if rest.len() >= 8 => (
+                Some(i64::from_be_bytes(rest[0..8].try_into().unwrap())),
+                &rest[8..],
+            ),
+            _ => return Err(Error::InvalidData),
+        };
+        if data.len() % 8 != 0 {
+            return Err(Error::InvalidData);
+        }
+        Ok(Expiry {
+            mid,
+            expire_at,
+            conversation,
+            thread,
+            uids: data
+                .chunks_exact(8)
+                .map(|uid| i64::from_be_bytes(uid.try_into().unwrap()))
+                .collect(),
+        })
+    }
+}
+
+impl<'a> Messages<'a> {
+    /// 设置会话的消息保留时长（秒），None表示消息不过期
+    pub fn set_ttl(&self, conversation: Conversation, ttl: Option<i64>) -> Result<()> {
+        match ttl {
+            Some(ttl) => self
+                .db
+                .db
+                .insert(key_ttl(conversation), ttl.to_be_bytes())?,
+            None => self.db.db.remove(key_ttl(conversation))?,
+        };
+        Ok(())
+    }
+
+    /// 获取会话的消息保留时长（秒）
+    pub fn get_ttl(&self, conversation: Conversation) -> Result<Option<i64>> {
+        self.db
+            .db
+            .get(key_ttl(conversation))?
+            .map(|data| {
+                Ok(i64::from_be_bytes(
+                    data.as_ref().try_into().map_err(|_| Error::InvalidData)?,
+                ))
+            })
+            .transpose()
+    }
+
+    /// 登记到期的消息，到期后由remove_expired删除
+    pub fn expire_msg(&self, expiry: &Expiry) -> Result<()> {
+        self.db
+            .db
+            .insert(key_expiry(expiry.expire_at, expiry.mid), expiry.to_bytes())?;
+        Ok(())
+    }
+
+    /// 获取到期时间不晚于now的最多limit条消息，按到期时间升序返回
+    pub fn fetch_expired(&self, now: i64, limit: usize) -> Result<Vec<Expiry>> {
+        let iter = self
+            .db
+            .db
+            .range(key_expiry(i64::MIN, 0)..key_expiry(now, i64::MAX))
+            .take(limit);
+        let mut expiries = Vec::new();
+        for item in iter {
+            let (key, value) = item?;
+            let expire_at = (u64::from_be_bytes(key[4..12].try_into().unwrap()) ^ (1 << 63)) as i64;
+            let mid = i64::from_be_bytes(key[12..20].try_into().unwrap());
+            expiries.push(Expiry::from_bytes(mid, expire_at, &value)?);
+        }
+        Ok(expiries)
+    }
+
+    /// 删除到期的消息，包括消息本身、收件箱、会话时间线、话题以及回应、置顶、@、隐藏、回执、历史版本等附属数据，
+    /// merged为合并转发消息的聊天记录id，attachments为消息（含合并转发的聊天记录）引用的附件
+    pub fn remove_expired(
+        &self,
+        expiry: &Expiry,
+        merged: Option<i64>,
+        attachments: &[i64],
+    ) -> Result<()> {
+        let mid = expiry.mid;
+        let mut batch = Batch::default();
+        batch.remove(key_msg(mid));
+        for &uid in &expiry.uids {
+            batch.remove(key_user_msg(uid, mid));
+            batch.remove(key_hidden(uid, mid));
+        }
+        match (expiry.conversation, expiry.thread) {
+            (_, Some(root)) => batch.remove(key_thread_msg(root, mid)),
+            (Conversation::Dm(a, b), None) => batch.remove(key_dm_msg(a, b, mid)),
+            (Conversation::Group(gid), None) => {
+                batch.remove(key_group_msg(gid, mid));
+                for &uid in &expiry.uids {
+                    batch.remove(key_mention(uid, gid, mid));
+                }
+            }
+        }
+        batch.remove(key_thread_stat(mid));
+        batch.remove(key_delivered(mid));
+        batch.remove(key_read(mid));
+        for item in self.db.db.scan_prefix(key_thread_subscriber_prefix(mid)) {
+            let (key, _) = item?;
+            batch.remove(key);
+        }
+        for item in self.db.db.scan_prefix(key_revision_prefix(mid)) {
+            let (key, _) = item?;
+            batch.remove(key);
+        }
+        for &attachment_id in attachments {
+            batch.remove(key_attachment_ref(attachment_id, mid));
+        }
+        if let Some(merged) = merged {
+            batch.remove(key_merged_msg(merged));
+        }
+        batch.remove(key_expiry(expiry.expire_at, mid));
+        self.db.db.apply_batch(batch)?;
+        self.remove_reactions(mid)?;
+        self.unpin_msg(expiry.conversation, mid)?;
+        Ok(())
+    }
+}
+
+fn key_ttl(conversation: Conversation) -> Vec<u8> {
+    let mut data = b"TTL/".to_vec();
+    data.extend_from_slice(&conversation.to_bytes());
+    data
+}
+
+/// 按到期时间排序的队列，时间戳转为无符号数以保证字节序与数值序一致
+fn key_expiry(expire_at: i64, msg_id: i64) -> [u8; 20] {
+    let mut data = [0; 20];
+    data[0..4].copy_from_slice(b"EXP/");
+    data[4..12].copy_from_slice(&((expire_at as u64) ^ (1 << 63)).to_be_bytes());
+    data[12..20].copy_from_slice(&msg_id.to_be_bytes());
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::open_temp_db;
+    use crate::{Conversation, Expiry};
+
+    #[test]
+    fn expired_messages_removed() {
+        let (_dir, db) = open_temp_db();
+        let dm = Conversation::dm(1, 2);
+        db.messages().set_ttl(dm, Some(60)).unwrap();
+        assert_eq!(
+            db.messages().get_ttl(Conversation::dm(2, 1)).unwrap(),
+            Some(60)
+        );
+        let mids = (0..3)
+            .map(|_| db.messages().send_to_dm(1, 2, b"secret").unwrap())
+            .collect::<Vec<i64>>();
+        for (i, &mid) in mids.iter().enumerate() {
+            db.messages()
+                .expire_msg(&Expiry {
+                    mid,
+                    expire_at: 1000 * (3 - i as i64),
+                    conversation: dm,
+                    thread: None,
+                    uids: vec![1, 2],
+                })
+                .unwrap();
+        }
+        db.messages().add_reaction(mids[2], 1, "👍").unwrap();
+        db.messages().pin_msg(dm, mids[2], 1, 1000).unwrap();
+        db.messages().insert_attachment_ref(7, mids[2]).unwrap();
+        db.messages().insert_attachment_ref(7, mids[0]).unwrap();
+
+        let expired = db.messages().fetch_expired(2000, 10).unwrap();
+        assert_eq!(
+            expired.iter().map(|x| x.mid).collect::<Vec<i64>>(),
+            vec![mids[2], mids[1]]
+        );
+        assert_eq!(expired[0].uids, vec![1, 2]);
+        for expiry in &expired {
+            db.messages().remove_expired(expiry, None, &[7]).unwrap();
+        }
+        assert!(db.messages().get(mids[2]).unwrap().is_none());
+        assert!(!db.messages().is_received(2, mids[2]).unwrap());
+        assert!(db.messages().fetch_reactions(mids[2]).unwrap().is_empty());
+        assert!(db.messages().fetch_pins(dm).unwrap().is_empty());
+        assert_eq!(
+            db.messages().fetch_attachment_refs(7).unwrap(),
+            vec![mids[0]]
+        );
+        let rest = db
+            .messages()
+            .fetch_dm_messages_before(1, 2, None, 10)
+            .unwrap();
+        assert_eq!(
+            rest.iter().map(|(mid, _)| *mid).collect::<Vec<i64>>(),
+            mids[0..1]
+        );
+        assert_eq!(db.messages().fetch_expired(2000, 10).unwrap(), vec![]);
+
+        db.messages().set_ttl(dm, None).unwrap();
+        assert_eq!(db.messages().get_ttl(dm).unwrap(), None);
+    }
+}
@@ -0,0 +1,84 @@
+use crate::{Conversation, Error, Messages, Result};
+
+impl<'a> Messages<'a> {
+    /// 对用户隐藏消息，不影响其他用户
+    pub fn hide_msg(&self, uid: i64, mid: i64) -> Result<()> {
+        self.db.db.insert(key_hidden(uid, mid), [])?;
+        Ok(())
+    }
+
+    /// 消息是否对用户隐藏
+    pub fn is_hidden(&self, uid: i64, mid: i64) -> Result<bool> {
+        Ok(self.db.db.contains_key(key_hidden(uid, mid))?)
+    }
+
+    /// 清空用户的会话，mid及之前的消息对该用户不可见，清空位置只会向后移动
+    pub fn clear_conversation(&self, uid: i64, conversation: Conversation, mid: i64) -> Result<()> {
+        self.db
+            .db
+            .update_and_fetch(key_cleared(uid, conversation), |data| {
+                let cleared = data
+                    .and_then(|data| data.try_into().ok())
+                    .map(i64::from_be_bytes)
+                    .map_or(mid, |cleared| cleared.max(mid));
+                Some(cleared.to_be_bytes().to_vec())
+            })?;
+        Ok(())
+    }
+
+    /// 获取用户会话的清空位置
+    pub fn get_cleared(&self, uid: i64, conversation: Conversation) -> Result<Option<i64>> {
+        self.db
+            .db
+            .get(key_cleared(uid, conversation))?
+            .map(|data| {
+                Ok(i64::from_be_bytes(
+                    data.as_ref().try_into().map_err(|_| Error::InvalidData)?,
+                ))
+            })
+            .transpose()
+    }
+}
+
+pub(crate) fn key_hidden(uid: i64, msg_id: i64) -> [u8; 21] {
+    let mut data = [0; 21];
+    data[0..5].copy_from_slice(b"HIDE/");
+    data[5..13].copy_from_slice(&uid.to_be_bytes());
+    data[13..21].copy_from_slice(&msg_id.to_be_bytes());
+    data
+}
+
+fn key_cleared(uid: i64, conversation: Conversation) -> Vec<u8> {
+    let mut data = b"CLR/".to_vec();
+    data.extend_from_slice(&uid.to_be_bytes());
+    data.extend_from_slice(&conversation.to_bytes());
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::open_temp_db;
+    use crate::Conversation;
+
+    #[test]
+    fn hidden_and_cleared_per_user() {
+        let (_dir, db) = open_temp_db();
+        let dm = Conversation::dm(1, 2);
+        let mid = db.messages().send_to_dm(1, 2, b"hello!").unwrap();
+        db.messages().hide_msg(1, mid).unwrap();
+        assert!(db.messages().is_hidden(1, mid).unwrap());
+        assert!(!db.messages().is_hidden(2, mid).unwrap());
+
+        assert_eq!(db.messages().get_cleared(1, dm).unwrap(), None);
+        db.messages().clear_conversation(1, dm, 10).unwrap();
+        db.messages().clear_conversation(1, dm, 5).unwrap();
+        assert_eq!(db.messages().get_cleared(1, dm).unwrap(), Some(10));
+        assert_eq!(db.messages().get_cleared(2, dm).unwrap(), None);
+        assert_eq!(
+            db.messages()
+                .get_cleared(1, Conversation::Group(1))
+                .unwrap(),
+            None
+        );
+    }
+}
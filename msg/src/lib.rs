@@ -1,14 +1,43 @@
+mod conversation;
 mod db;
+mod dedup;
 mod error;
+mod expire;
+mod hide;
+mod mention;
 mod messages;
+mod pin;
+mod reaction;
+mod receipt;
+mod schedule;
+mod search;
 mod sequence;
+mod thread;
 
+pub use conversation::Conversation;
 pub use db::MsgDb;
+pub use dedup::ClientKey;
 pub use error::{Error, Result};
+pub use expire::Expiry;
 pub use messages::Messages;
+pub use pin::Pin;
+pub use receipt::Receipt;
+pub use schedule::Schedule;
+pub use search::tokenize;
+pub use thread::ThreadStat;
 
 #[cfg(test)]
 mod test {
+    use tempfile::{tempdir, TempDir};
+
+    use crate::MsgDb;
+
+    /// 在临时目录中打开消息库，返回的目录需与消息库同时存活
+    pub(crate) fn open_temp_db() -> (TempDir, MsgDb) {
+        let dir = tempdir().unwrap();
+        let db = MsgDb::open(dir.path()).unwrap();
+        (dir, db)
+    }
 
     #[test]
     fn send_msg() {}
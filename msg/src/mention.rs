@@ -0,0 +1,108 @@
+use sled::Batch;
+
+use crate::{Messages, Result};
+
+impl<'a> Messages<'a> {
+    /// 记录群消息@到的用户
+    pub fn insert_mentions(
+        &self,
+        gid: i64,
+        mid: i64,
+        uids: impl IntoIterator<Item = i64>,
+    ) -> Result<()> {
+        let mut batch = Batch::default();
+        for uid in uids {
+            batch.insert(key_mention(uid, gid, mid), []);
+        }
+        self.db.db.apply_batch(batch)?;
+        Ok(())
+    }
+
+    /// 删除群消息@用户的记录，用于撤回消息
+    pub fn remove_mentions(
+        &self,
+        gid: i64,
+        mid: i64,
+        uids: impl IntoIterator<Item = i64>,
+    ) -> Result<()> {
+        let mut batch = Batch::default();
+        for uid in uids {
+            batch.remove(key_mention(uid, gid, mid));
+        }
+        self.db.db.apply_batch(batch)?;
+        Ok(())
+    }
+
+    /// 群内after之后是否有@该用户的消息
+    pub fn has_mention_after(&self, uid: i64, gid: i64, after: Option<i64>) -> Result<bool> {
+        Ok(!self.fetch_mentions_after(uid, gid, after, 1)?.is_empty())
+    }
+
+    /// 获取群内after之后@该用户的limit条消息id，按消息id升序返回
+    pub fn fetch_mentions_after(
+        &self,
+        uid: i64,
+        gid: i64,
+        after: Option<i64>,
+        limit: usize,
+    ) -> Result<Vec<i64>> {
+        let after_id = after.map(|id| id + 1).unwrap_or_default();
+        let iter = self
+            .db
+            .db
+            .range(key_mention(uid, gid, after_id)..key_mention(uid, gid, i64::MAX))
+            .take(limit);
+        let mut mids = Vec::new();
+        for item in iter {
+            let (key, _) = item?;
+            mids.push(i64::from_be_bytes(key[20..28].try_into().unwrap()));
+        }
+        Ok(mids)
+    }
+}
+
+pub(crate) fn key_mention(uid: i64, gid: i64, msg_id: i64) -> [u8; 28] {
+    let mut data = [0; 28];
+    data[0..4].copy_from_slice(b"MTN/");
+    data[4..12].copy_from_slice(&uid.to_be_bytes());
+    data[12..20].copy_from_slice(&gid.to_be_bytes());
+    data[20..28].copy_from_slice(&msg_id.to_be_bytes());
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::open_temp_db;
+
+    #[test]
+    fn mentions_after_read_index() {
+        let (_dir, db) = open_temp_db();
+        let mids = (0..3)
+            .map(|_| db.messages().send_to_group(1, [1, 2, 3], b"@2").unwrap())
+            .collect::<Vec<i64>>();
+        for &mid in &mids {
+            db.messages().insert_mentions(1, mid, [2]).unwrap();
+        }
+        db.messages().insert_mentions(9, mids[2] + 1, [2]).unwrap();
+        assert_eq!(
+            db.messages()
+                .fetch_mentions_after(2, 1, Some(mids[0]), 10)
+                .unwrap(),
+            mids[1..3]
+        );
+        assert!(db.messages().has_mention_after(2, 1, None).unwrap());
+        assert!(!db
+            .messages()
+            .has_mention_after(2, 1, Some(mids[2]))
+            .unwrap());
+        assert!(!db.messages().has_mention_after(3, 1, None).unwrap());
+
+        db.messages().remove_mentions(1, mids[2], [2, 3]).unwrap();
+        assert_eq!(
+            db.messages()
+                .fetch_mentions_after(2, 1, Some(mids[0]), 10)
+                .unwrap(),
+            mids[1..2]
+        );
+    }
+}
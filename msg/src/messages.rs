@@ -11,6 +11,16 @@ impl<'a> Messages<'a> {
         Ok(self.db.db.get(key_msg(mid))?.map(|data| data.to_vec()))
     }
 
+    /// 用户是否收到过该消息
+    pub fn is_received(&self, uid: i64, mid: i64) -> Result<bool> {
+        Ok(self.db.db.contains_key(key_user_msg(uid, mid))?)
+    }
+
+    /// 生成消息id，用于在消息发送前确定id的场景（如合并转发的快照）
+    pub fn generate_id(&self) -> Result<i64> {
+        self.db.generate_msg_id()
+    }
+
     /// 发消息到群组
     pub fn send_to_group(
         &self,
@@ -42,19 +52,57 @@ impl<'a> Messages<'a> {
         Ok(id)
     }
 
-    /// 获取用户的after之后的最近limit条消息（所有消息，包括单聊和群聊消息）
+    /// 覆盖单聊消息（撤回、编辑等场景），消息id不变
+    pub fn replace_dm_msg(&self, from_uid: i64, to_uid: i64, mid: i64, msg: &[u8]) -> Result<()> {
+        let mut batch = Batch::default();
+        batch.insert(key_msg(mid), msg);
+        for target_uid in [from_uid, to_uid] {
+            batch.insert(key_user_msg(target_uid, mid), msg);
+        }
+        batch.insert(key_dm_msg(from_uid, to_uid, mid), msg);
+        self.db.db.apply_batch(batch)?;
+        Ok(())
+    }
+
+    /// 覆盖群消息（撤回、编辑等场景），消息id不变，只覆盖发送时已收到该消息的用户
+    pub fn replace_group_msg(
+        &self,
+        gid: i64,
+        to: impl IntoIterator<Item = i64>,
+        mid: i64,
+        msg: &[u8],
+    ) -> Result<()> {
+        let mut batch = Batch::default();
+        batch.insert(key_msg(mid), msg);
+        for target_uid in to {
+            let key = key_user_msg(target_uid, mid);
+            if self.db.db.contains_key(key)? {
+                batch.insert(key, msg);
+            }
+        }
+        batch.insert(key_group_msg(gid, mid), msg);
+        self.db.db.apply_batch(batch)?;
+        Ok(())
+    }
+
+    /// 获取用户的after之后的limit条消息（所有消息，包括单聊和群聊消息），按消息id升序返回，可用于逐页拉取
     pub fn fetch_user_messages_after(
         &self,
         uid: i64,
         after: Option<i64>,
         limit: usize,
     ) -> Result<Vec<(i64, Vec<u8>)>> {
-        let after_id = after.map(|id| id + 1).unwrap_or_default();
+        let after_id = match after {
+            Some(id) => match id.checked_add(1) {
+                Some(id) => id,
+                None => return Ok(vec![]),
+            },
+            None => 0,
+        };
         let iter = self
             .db
             .db
-            .range(key_user_msg(uid, after_id)..key_user_msg(uid, i64::MAX))
-            .rev();
+            .range(key_user_msg(uid, after_id)..key_user_msg(uid, i64::MAX));
         let mut msgs = Vec::new();
 
         for item in iter.take(limit) {
@@ -71,7 +119,6 @@ impl<'a> Messages<'a> {
             msgs.push((msg_id, value.to_vec()));
         }
 
-        msgs.reverse();
         Ok(msgs)
     }
 
@@ -110,12 +157,46 @@ impl<'a> Messages<'a> {
         Ok(msgs)
     }
 
+    /// 获取单聊消息，after之后的limit条消息，按消息id升序返回
+    pub fn fetch_dm_messages_after(
+        &self,
+        from_uid: i64,
+        to_uid: i64,
+        after: i64,
+        limit: usize,
+    ) -> Result<Vec<(i64, Vec<u8>)>> {
+        let Some(after_id) = after.checked_add(1) else {
+            return Ok(vec![]);
+        };
+        let iter = self
+            .db
+            .db
+            .range(key_dm_msg(from_uid, to_uid, after_id)..key_dm_msg(from_uid, to_uid, i64::MAX))
+            .take(limit);
+        let mut msgs = Vec::new();
+
+        for item in iter {
+            let (key, value) = item?;
+            let (_, _, msg_id) = match decode_key_dm_msg(&key) {
+                Some(res) => res,
+                None => break,
+            };
+
+            msgs.push((msg_id, value.to_vec()));
+        }
+
+        Ok(msgs)
+    }
+
     /// 统计after之后的单聊消息数量
     pub fn count_dm_messages_after(&self, from_uid: i64, to_uid: i64, after: i64) -> Result<usize> {
+        let Some(after_id) = after.checked_add(1) else {
+            return Ok(0);
+        };
         Ok(self
             .db
             .db
-            .range(key_dm_msg(from_uid, to_uid, after + 1)..key_dm_msg(from_uid, to_uid, i64::MAX))
+            .range(key_dm_msg(from_uid, to_uid, after_id)..key_dm_msg(from_uid, to_uid, i64::MAX))
             .count())
     }
 
@@ -153,6 +234,36 @@ impl<'a> Messages<'a> {
         Ok(msgs)
     }
 
+    /// 获取群聊消息，after之后的limit条消息，按消息id升序返回
+    pub fn fetch_group_messages_after(
+        &self,
+        gid: i64,
+        after: i64,
+        limit: usize,
+    ) -> Result<Vec<(i64, Vec<u8>)>> {
+        let Some(after_id) = after.checked_add(1) else {
+            return Ok(vec![]);
+        };
+        let iter = self
+            .db
+            .db
+            .range(key_group_msg(gid, after_id)..key_group_msg(gid, i64::MAX))
+            .take(limit);
+        let mut msgs = Vec::new();
+
+        for item in iter {
+            let (key, value) = item?;
+            let (_, msg_id) = match decode_key_group_msg(&key) {
+                Some(res) => res,
+                None => break,
+            };
+
+            msgs.push((msg_id, value.to_vec()));
+        }
+
+        Ok(msgs)
+    }
+
     /// 统计after之后的群消息数量
     pub fn count_group_messages_after(&self, gid: i64, after: i64) -> Result<usize> {
         Ok(self
@@ -162,6 +273,47 @@ impl<'a> Messages<'a> {
             .count())
     }
 
+    /// 保存消息的历史版本，版本号取自递增的id序列，并发保存也不会互相覆盖
+    pub fn insert_revision(&self, mid: i64, msg: &[u8]) -> Result<()> {
+        let rev = self.db.generate_msg_id()?;
+        self.db.db.insert(key_revision(mid, rev), msg)?;
+        Ok(())
+    }
+
+    /// 获取消息的所有历史版本，按保存顺序返回
+    pub fn fetch_revisions(&self, mid: i64) -> Result<Vec<Vec<u8>>> {
+        let mut revisions = Vec::new();
+        for item in self.db.db.scan_prefix(key_revision_prefix(mid)) {
+            let (_, value) = item?;
+            revisions.push(value.to_vec());
+        }
+        Ok(revisions)
+    }
+
+    /// 记录引用了附件的消息
+    pub fn insert_attachment_ref(&self, attachment_id: i64, mid: i64) -> Result<()> {
+        self.db
+            .db
+            .insert(key_attachment_ref(attachment_id, mid), [])?;
+        Ok(())
+    }
+
+    /// 获取引用了附件的所有消息id
+    pub fn fetch_attachment_refs(&self, attachment_id: i64) -> Result<Vec<i64>> {
+        let mut mids = Vec::new();
+        for item in self
+            .db
+            .db
+            .scan_prefix(key_attachment_ref_prefix(attachment_id))
+        {
+            let (key, _) = item?;
+            if let Some((_, mid)) = decode_key_attachment_ref(&key) {
+                mids.push(mid);
+            }
+        }
+        Ok(mids)
+    }
+
     /// 插入消息
     pub fn insert_merged_msg(&self, mid: i64, msg: &[u8]) -> Result<()> {
         self.db.db.insert(key_merged_msg(mid), msg)?;
@@ -192,21 +344,59 @@ impl<'a> Messages<'a> {
     }
 }
 
-fn key_msg(msg_id: i64) -> [u8; 12] {
+pub(crate) fn key_msg(msg_id: i64) -> [u8; 12] {
     let mut data = [0; 12];
     data[0..4].copy_from_slice(b"MSG/");
     data[4..12].copy_from_slice(&msg_id.to_be_bytes());
     data
 }
 
-fn key_merged_msg(msg_id: i64) -> [u8; 13] {
+pub(crate) fn key_merged_msg(msg_id: i64) -> [u8; 13] {
     let mut data = [0; 13];
     data[0..5].copy_from_slice(b"FMSG/");
     data[5..13].copy_from_slice(&msg_id.to_be_bytes());
     data
 }
 
-fn key_user_msg(uid: i64, msg_id: i64) -> [u8; 21] {
+pub(crate) fn key_revision_prefix(msg_id: i64) -> [u8; 13] {
+    let mut data = [0; 13];
+    data[0..5].copy_from_slice(b"RMSG/");
+    data[5..13].copy_from_slice(&msg_id.to_be_bytes());
+    data
+}
+
+fn key_revision(msg_id: i64, rev: i64) -> [u8; 21] {
+    let mut data = [0; 21];
+    data[0..13].copy_from_slice(&key_revision_prefix(msg_id));
+    data[13..21].copy_from_slice(&rev.to_be_bytes());
+    data
+}
+
+fn key_attachment_ref_prefix(attachment_id: i64) -> [u8; 12] {
+    let mut data = [0; 12];
+    data[0..4].copy_from_slice(b"ATT/");
+    data[4..12].copy_from_slice(&attachment_id.to_be_bytes());
+    data
+}
+
+pub(crate) fn key_attachment_ref(attachment_id: i64, msg_id: i64) -> [u8; 20] {
+    let mut data = [0; 20];
+    data[0..12].copy_from_slice(&key_attachment_ref_prefix(attachment_id));
+    data[12..20].copy_from_slice(&msg_id.to_be_bytes());
+    data
+}
+
+fn decode_key_attachment_ref(data: &[u8]) -> Option<(i64, i64)> {
+    let data = data.strip_prefix(b"ATT/")?;
+    if data.len() != 16 {
+        return None;
+    }
+    let attachment_id = i64::from_be_bytes(data[0..8].try_into().unwrap());
+    let msg_id = i64::from_be_bytes(data[8..16].try_into().unwrap());
+    Some((attachment_id, msg_id))
+}
+
+pub(crate) fn key_user_msg(uid: i64, msg_id: i64) -> [u8; 21] {
     let mut data = [0; 21];
     data[0..5].copy_from_slice(b"UMSG/");
     data[5..13].copy_from_slice(&uid.to_be_bytes());
@@ -224,7 +414,7 @@ fn decode_key_user_msg(data: &[u8]) -> Option<(i64, i64)> {
     Some((uid, msg_id))
 }
 
-fn key_group_msg(gid: i64, msg_id: i64) -> [u8; 21] {
+pub(crate) fn key_group_msg(gid: i64, msg_id: i64) -> [u8; 21] {
     let mut data = [0; 21];
     data[0..5].copy_from_slice(b"GMSG/");
     data[5..13].copy_from_slice(&gid.to_be_bytes());
@@ -242,7 +432,7 @@ fn decode_key_group_msg(data: &[u8]) -> Option<(i64, i64)> {
     Some((gid, msg_id))
 }
 
-fn key_dm_msg(from_uid: i64, to_uid: i64, msg_id: i64) -> [u8; 27] {
+pub(crate) fn key_dm_msg(from_uid: i64, to_uid: i64, msg_id: i64) -> [u8; 27] {
     let mut data = [0; 27];
     let a = from_uid.min(to_uid);
     let b = from_uid.max(to_uid);
@@ -263,3 +453,73 @@ fn decode_key_dm_msg(data: &[u8]) -> Option<(i64, i64, i64)> {
     let msg_id = i64::from_be_bytes(data[16..24].try_into().unwrap());
     Some((from_uid, to_uid, msg_id))
 }
+
+#[cfg(test)]
+mod test {
+    use crate::test::open_temp_db;
+
+    #[test]
+    fn fetch_user_messages_after_in_pages() {
+        let (_dir, db) = open_temp_db();
+        let mids = (0..5)
+            .map(|_| db.messages().send_to_dm(1, 2, b"hello!").unwrap())
+            .collect::<Vec<i64>>();
+        let first = db.messages().fetch_user_messages_after(2, None, 3).unwrap();
+        assert_eq!(
+            first.iter().map(|(mid, _)| *mid).collect::<Vec<i64>>(),
+            mids[0..3]
+        );
+        let rest = db
+            .messages()
+            .fetch_user_messages_after(2, Some(mids[2]), 3)
+            .unwrap();
+        assert_eq!(
+            rest.iter().map(|(mid, _)| *mid).collect::<Vec<i64>>(),
+            mids[3..5]
+        );
+    }
+
+    #[test]
+    fn fetch_dm_messages_around() {
+        let (_dir, db) = open_temp_db();
+        let mids = (0..5)
+            .map(|_| db.messages().send_to_dm(1, 2, b"hello!").unwrap())
+            .collect::<Vec<i64>>();
+        db.messages().send_to_dm(1, 3, b"other").unwrap();
+        let before = db
+            .messages()
+            .fetch_dm_messages_before(2, 1, Some(mids[2] + 1), 2)
+            .unwrap();
+        assert_eq!(
+            before.iter().map(|(mid, _)| *mid).collect::<Vec<i64>>(),
+            mids[1..3]
+        );
+        let after = db
+            .messages()
+            .fetch_dm_messages_after(2, 1, mids[2], 10)
+            .unwrap();
+        assert_eq!(
+            after.iter().map(|(mid, _)| *mid).collect::<Vec<i64>>(),
+            mids[3..5]
+        );
+        assert!(db
+            .messages()
+            .fetch_dm_messages_after(2, 1, i64::MAX, 10)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn revisions_in_saved_order() {
+        let (_dir, db) = open_temp_db();
+        let mid = db.messages().send_to_dm(1, 2, b"v0").unwrap();
+        for rev in [b"v0", b"v1", b"v2"] {
+            db.messages().insert_revision(mid, rev).unwrap();
+            db.messages().send_to_dm(1, 2, b"other").unwrap();
+        }
+        assert_eq!(
+            db.messages().fetch_revisions(mid).unwrap(),
+            vec![b"v0".to_vec(), b"v1".to_vec(), b"v2".to_vec()]
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use crate::{Conversation, Error, Messages, Result};
+
+/// 会话中置顶的消息
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pin {
+    pub mid: i64,
+    /// 置顶操作人id
+    pub pinned_by: i64,
+    /// 置顶时间，毫秒时间戳
+    pub pinned_at: i64,
+}
+
+impl<'a> Messages<'a> {
+    /// 置顶消息，返回是否为新置顶（已置顶时返回false且不更新置顶时间）
+    pub fn pin_msg(
+        &self,
+        conversation: Conversation,
+        mid: i64,
+        pinned_by: i64,
+        pinned_at: i64,
+    ) -> Result<bool> {
+        let mut value = [0; 16];
+        value[0..8].copy_from_slice(&pinned_by.to_be_bytes());
+        value[8..16].copy_from_slice(&pinned_at.to_be_bytes());
+        let result = self.db.db.compare_and_swap(
+            key_pin(conversation, mid),
+            None as Option<&[u8]>,
+            Some(&value[..]),
+        )?;
+        Ok(result.is_ok())
+    }
+
+    /// 取消置顶，返回消息是否曾置顶
+    pub fn unpin_msg(&self, conversation: Conversation, mid: i64) -> Result<bool> {
+        Ok(self.db.db.remove(key_pin(conversation, mid))?.is_some())
+    }
+
+    /// 获取会话中置顶的消息，按置顶时间倒序返回
+    pub fn fetch_pins(&self, conversation: Conversation) -> Result<Vec<Pin>> {
+        let prefix = key_pin_prefix(conversation);
+        let mut pins = Vec::new();
+        for item in self.db.db.scan_prefix(&prefix) {
+            let (key, value) = item?;
+            if key.len() != prefix.len() + 8 || value.len() != 16 {
+                return Err(Error::InvalidData);
+            }
+            pins.push(Pin {
+                mid: i64::from_be_bytes(key[prefix.len()..].try_into().unwrap()),
+                pinned_by: i64::from_be_bytes(value[0..8].try_into().unwrap()),
+                pinned_at: i64::from_be_bytes(value[8..16].try_into().unwrap()),
+            });
+        }
+        pins.sort_by_key(|pin| std::cmp::Reverse((pin.pinned_at, pin.mid)));
+        Ok(pins)
+    }
+}
+
+fn key_pin_prefix(conversation: Conversation) -> Vec<u8> {
+    let mut data = b"PIN/".to_vec();
+    data.extend_from_slice(&conversation.to_bytes());
+    data
+}
+
+fn key_pin(conversation: Conversation, msg_id: i64) -> Vec<u8> {
+    let mut data = key_pin_prefix(conversation);
+    data.extend_from_slice(&msg_id.to_be_bytes());
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::open_temp_db;
+    use crate::Conversation;
+
+    #[test]
+    fn pins_in_pinned_order() {
+        let (_dir, db) = open_temp_db();
+        let dm = Conversation::dm(1, 2);
+        let mids = (0..3)
+            .map(|_| db.messages().send_to_dm(1, 2, b"hello!").unwrap())
+            .collect::<Vec<i64>>();
+        assert!(db.messages().pin_msg(dm, mids[2], 1, 1000).unwrap());
+        assert!(db.messages().pin_msg(dm, mids[0], 2, 2000).unwrap());
+        assert!(!db.messages().pin_msg(dm, mids[0], 1, 3000).unwrap());
+        db.messages()
+            .pin_msg(Conversation::Group(1), mids[1], 1, 1000)
+            .unwrap();
+        let pins = db.messages().fetch_pins(Conversation::dm(2, 1)).unwrap();
+        assert_eq!(
+            pins.iter().map(|pin| pin.mid).collect::<Vec<i64>>(),
+            vec![mids[0], mids[2]]
+        );
+        assert_eq!((pins[0].pinned_by, pins[0].pinned_at), (2, 2000));
+
+        assert!(db.messages().unpin_msg(dm, mids[0]).unwrap());
+        assert!(!db.messages().unpin_msg(dm, mids[0]).unwrap());
+        assert_eq!(db.messages().fetch_pins(dm).unwrap().len(), 1);
+    }
+}
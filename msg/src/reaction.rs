@@ -0,0 +1,96 @@
+use sled::Batch;
+
+use crate::{Error, Messages, Result};
+
+impl<'a> Messages<'a> {
+    /// 添加表情回应，返回是否为新增（重复回应同一表情时返回false）
+    pub fn add_reaction(&self, mid: i64, uid: i64, emoji: &str) -> Result<bool> {
+        Ok(self
+            .db
+            .db
+            .insert(key_reaction(mid, emoji, uid), [])?
+            .is_none())
+    }
+
+    /// 取消表情回应，返回是否存在该回应
+    pub fn remove_reaction(&self, mid: i64, uid: i64, emoji: &str) -> Result<bool> {
+        Ok(self.db.db.remove(key_reaction(mid, emoji, uid))?.is_some())
+    }
+
+    /// 删除消息的所有表情回应
+    pub fn remove_reactions(&self, mid: i64) -> Result<()> {
+        let mut batch = Batch::default();
+        for item in self.db.db.scan_prefix(key_reaction_prefix(mid)) {
+            let (key, _) = item?;
+            batch.remove(key);
+        }
+        self.db.db.apply_batch(batch)?;
+        Ok(())
+    }
+
+    /// 获取消息的表情回应，按表情分组，返回表情及回应的用户id
+    pub fn fetch_reactions(&self, mid: i64) -> Result<Vec<(String, Vec<i64>)>> {
+        let mut reactions: Vec<(String, Vec<i64>)> = Vec::new();
+        for item in self.db.db.scan_prefix(key_reaction_prefix(mid)) {
+            let (key, _) = item?;
+            let (emoji, uid) = decode_key_reaction(&key).ok_or(Error::InvalidData)?;
+            match reactions.last_mut() {
+                Some((last, uids)) if *last == emoji => uids.push(uid),
+                _ => reactions.push((emoji, vec![uid])),
+            }
+        }
+        Ok(reactions)
+    }
+}
+
+fn key_reaction_prefix(msg_id: i64) -> [u8; 12] {
+    let mut data = [0; 12];
+    data[0..4].copy_from_slice(b"RCT/");
+    data[4..12].copy_from_slice(&msg_id.to_be_bytes());
+    data
+}
+
+fn key_reaction(msg_id: i64, emoji: &str, uid: i64) -> Vec<u8> {
+    let mut data = Vec::with_capacity(12 + emoji.len() + 9);
+    data.extend_from_slice(&key_reaction_prefix(msg_id));
+    data.extend_from_slice(emoji.as_bytes());
+    data.push(0);
+    data.extend_from_slice(&uid.to_be_bytes());
+    data
+}
+
+fn decode_key_reaction(data: &[u8]) -> Option<(String, i64)> {
+    let data = data.strip_prefix(b"RCT/")?.get(8..)?;
+    let (emoji, uid) = data.split_at(data.len().checked_sub(9)?);
+    let uid = uid.strip_prefix(&[0])?;
+    let emoji = String::from_utf8(emoji.to_vec()).ok()?;
+    Some((emoji, i64::from_be_bytes(uid.try_into().ok()?)))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::open_temp_db;
+
+    #[test]
+    fn reactions_grouped_by_emoji() {
+        let (_dir, db) = open_temp_db();
+        let mid = db.messages().send_to_dm(1, 2, b"hello!").unwrap();
+        assert!(db.messages().add_reaction(mid, 1, "👍").unwrap());
+        assert!(!db.messages().add_reaction(mid, 1, "👍").unwrap());
+        assert!(db.messages().add_reaction(mid, 2, "👍").unwrap());
+        assert!(db.messages().add_reaction(mid, 2, "❤️").unwrap());
+        db.messages().add_reaction(mid + 1, 1, "👍").unwrap();
+        let mut reactions = db.messages().fetch_reactions(mid).unwrap();
+        reactions.sort();
+        assert_eq!(
+            reactions,
+            vec![("❤️".to_string(), vec![2]), ("👍".to_string(), vec![1, 2])]
+        );
+
+        assert!(db.messages().remove_reaction(mid, 1, "👍").unwrap());
+        assert!(!db.messages().remove_reaction(mid, 1, "👍").unwrap());
+        db.messages().remove_reactions(mid).unwrap();
+        assert!(db.messages().fetch_reactions(mid).unwrap().is_empty());
+        assert_eq!(db.messages().fetch_reactions(mid + 1).unwrap().len(), 1);
+    }
+}
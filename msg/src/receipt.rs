@@ -0,0 +1,108 @@
+use crate::{Error, Messages, Result};
+
+/// 单聊消息的回执，时间为毫秒时间戳
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Receipt {
+    pub delivered_at: Option<i64>,
+    pub read_at: Option<i64>,
+}
+
+impl<'a> Messages<'a> {
+    /// 记录消息已送达，返回是否为首次送达
+    pub fn mark_delivered(&self, mid: i64, delivered_at: i64) -> Result<bool> {
+        let result = self.db.db.compare_and_swap(
+            key_delivered(mid),
+            None as Option<&[u8]>,
+            Some(&delivered_at.to_be_bytes()[..]),
+        )?;
+        Ok(result.is_ok())
+    }
+
+    /// 记录消息已读，已读的消息同时视为已送达，返回首次已读的消息id
+    pub fn mark_read(&self, mids: impl IntoIterator<Item = i64>, read_at: i64) -> Result<Vec<i64>> {
+        let mut read = Vec::new();
+        for mid in mids {
+            self.mark_delivered(mid, read_at)?;
+            let result = self.db.db.compare_and_swap(
+                key_read(mid),
+                None as Option<&[u8]>,
+                Some(&read_at.to_be_bytes()[..]),
+            )?;
+            if result.is_ok() {
+                read.push(mid);
+            }
+        }
+        Ok(read)
+    }
+
+    /// 获取消息的回执
+    pub fn get_receipt(&self, mid: i64) -> Result<Receipt> {
+        let get = |key: [u8; 12]| -> Result<Option<i64>> {
+            self.db
+                .db
+                .get(key)?
+                .map(|data| {
+                    Ok(i64::from_be_bytes(
+                        data.as_ref().try_into().map_err(|_| Error::InvalidData)?,
+                    ))
+                })
+                .transpose()
+        };
+        Ok(Receipt {
+            delivered_at: get(key_delivered(mid))?,
+            read_at: get(key_read(mid))?,
+        })
+    }
+}
+
+pub(crate) fn key_delivered(msg_id: i64) -> [u8; 12] {
+    let mut data = [0; 12];
+    data[0..4].copy_from_slice(b"DLV/");
+    data[4..12].copy_from_slice(&msg_id.to_be_bytes());
+    data
+}
+
+pub(crate) fn key_read(msg_id: i64) -> [u8; 12] {
+    let mut data = [0; 12];
+    data[0..4].copy_from_slice(b"RED/");
+    data[4..12].copy_from_slice(&msg_id.to_be_bytes());
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::open_temp_db;
+    use crate::Receipt;
+
+    #[test]
+    fn receipts_set_once() {
+        let (_dir, db) = open_temp_db();
+        let mids = (0..2)
+            .map(|_| db.messages().send_to_dm(1, 2, b"hello!").unwrap())
+            .collect::<Vec<i64>>();
+        assert_eq!(
+            db.messages().get_receipt(mids[0]).unwrap(),
+            Receipt::default()
+        );
+        assert!(db.messages().mark_delivered(mids[0], 1000).unwrap());
+        assert!(!db.messages().mark_delivered(mids[0], 2000).unwrap());
+
+        assert_eq!(db.messages().mark_read(mids.clone(), 3000).unwrap(), mids);
+        assert!(db
+            .messages()
+            .mark_read(mids.clone(), 4000)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            db.messages().get_receipt(mids[0]).unwrap(),
+            Receipt {
+                delivered_at: Some(1000),
+                read_at: Some(3000),
+            }
+        );
+        assert_eq!(
+            db.messages().get_receipt(mids[1]).unwrap().delivered_at,
+            Some(3000)
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use sled::Batch;
+
+use crate::{Error, Messages, Result};
+
+/// 定时发送的消息，data由调用方序列化
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    pub id: i64,
+    /// 发送者id
+    pub uid: i64,
+    /// 发送时间，毫秒时间戳
+    pub send_at: i64,
+    pub data: Vec<u8>,
+}
+
+impl Schedule {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(16 + self.data.len());
+        data.extend_from_slice(&self.uid.to_be_bytes());
+        data.extend_from_slice(&self.send_at.to_be_bytes());
+        data.extend_from_slice(&self.data);
+        data
+    }
+
+    fn from_bytes(id: i64, data: &[u8]) -> Result<Self> {
+        if data.len() < 16 {
+            return Err(Error::InvalidData);
+        }
+        Ok(Schedule {
+            id,
+            uid: i64::from_be_bytes(data[0..8].try_into().unwrap()),
+            send_at: i64::from_be_bytes(data[8..16].try_into().unwrap()),
+            data: data[16..].to_vec(),
+        })
+    }
+}
+
+impl<'a> Messages<'a> {
+    /// 添加定时消息，返回定时消息id
+    pub fn insert_schedule(&self, uid: i64, send_at: i64, data: &[u8]) -> Result<i64> {
+        let schedule = Schedule {
+            id: self.db.generate_msg_id()?,
+            uid,
+            send_at,
+            data: data.to_vec(),
+        };
+        let mut batch = Batch::default();
+        batch.insert(key_schedule(schedule.id), schedule.to_bytes());
+        batch.insert(key_schedule_queue(send_at, schedule.id), []);
+        batch.insert(key_user_schedule(uid, schedule.id), []);
+        self.db.db.apply_batch(batch)?;
+        Ok(schedule.id)
+    }
+
+    pub fn get_schedule(&self, id: i64) -> Result<Option<Schedule>> {
+        self.db
+            .db
+            .get(key_schedule(id))?
+            .map(|data| Schedule::from_bytes(id, &data))
+            .transpose()
+    }
+
+    /// 修改定时消息的发送时间与内容，定时消息已被发送或取消时返回false
+    pub fn update_schedule(&self, old: &Schedule, send_at: i64, data: &[u8]) -> Result<bool> {
+        let schedule = Schedule {
+            send_at,
+            data: data.to_vec(),
+            ..old.clone()
+        };
+        let swapped = self.db.db.compare_and_swap(
+            key_schedule(old.id),
+            Some(old.to_bytes()),
+            Some(schedule.to_bytes()),
+        )?;
+        if swapped.is_err() {
+            return Ok(false);
+        }
+        let mut batch = Batch::default();
+        batch.remove(key_schedule_queue(old.send_at, old.id));
+        batch.insert(key_schedule_queue(send_at, old.id), []);
+        self.db.db.apply_batch(batch)?;
+        Ok(true)
+    }
+
+    /// 删除定时消息，返回被删除的定时消息。并发删除同一定时消息时只有一方能取得，可用于认领待发送的消息
+    pub fn remove_schedule(&self, id: i64) -> Result<Option<Schedule>> {
+        let Some(data) = self.db.db.remove(key_schedule(id))? else {
+            return Ok(None);
+        };
+        let schedule = Schedule::from_bytes(id, &data)?;
+        let mut batch = Batch::default();
+        batch.remove(key_schedule_queue(schedule.send_at, id));
+        batch.remove(key_user_schedule(schedule.uid, id));
+        self.db.db.apply_batch(batch)?;
+        Ok(Some(schedule))
+    }
+
+    /// 获取用户所有待发送的定时消息，按id升序返回
+    pub fn fetch_user_schedules(&self, uid: i64) -> Result<Vec<Schedule>> {
+        let mut schedules = Vec::new();
+        for item in self.db.db.scan_prefix(key_user_schedule_prefix(uid)) {
+            let (key, _) = item?;
+            let id = i64::from_be_bytes(key[13..21].try_into().unwrap());
+            schedules.extend(self.get_schedule(id)?);
+        }
+        Ok(schedules)
+    }
+
+    /// 获取发送时间不晚于now的最多limit条定时消息，按发送时间升序返回
+    pub fn fetch_due_schedules(&self, now: i64, limit: usize) -> Result<Vec<Schedule>> {
+        let iter = self
+            .db
+            .db
+            .range(key_schedule_queue(i64::MIN, 0)..key_schedule_queue(now, i64::MAX));
+        let mut schedules = Vec::new();
+        for item in iter {
+            if schedules.len() >= limit {
+                break;
+            }
+            let (key, _) = item?;
+            let id = i64::from_be_bytes(key[13..21].try_into().unwrap());
+            schedules.extend(self.get_schedule(id)?);
+        }
+        Ok(schedules)
+    }
+}
+
+fn key_schedule(id: i64) -> [u8; 12] {
+    let mut data = [0; 12];
+    data[0..4].copy_from_slice(b"SCH/");
+    data[4..12].copy_from_slice(&id.to_be_bytes());
+    data
+}
+
+/// 按发送时间排序的队列，时间戳转为无符号数以保证字节序与数值序一致
+fn key_schedule_queue(send_at: i64, id: i64) -> [u8; 21] {
+    let mut data = [0; 21];
+    data[0..5].copy_from_slice(b"SCHQ/");
+    data[5..13].copy_from_slice(&((send_at as u64) ^ (1 << 63)).to_be_bytes());
+    data[13..21].copy_from_slice(&id.to_be_bytes());
+    data
+}
+
+fn key_user_schedule_prefix(uid: i64) -> [u8; 13] {
+    let mut data = [0; 13];
+    data[0..5].copy_from_slice(b"USCH/");
+    data[5..13].copy_from_slice(&uid.to_be_bytes());
+    data
+}
+
+fn key_user_schedule(uid: i64, id: i64) -> [u8; 21] {
+    let mut data = [0; 21];
+    data[0..13].copy_from_slice(&key_user_schedule_prefix(uid));
+    data[13..21].copy_from_slice(&id.to_be_bytes());
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::open_temp_db;
+
+    #[test]
+    fn schedules_due_in_order() {
+        let (_dir, db) = open_temp_db();
+        let late = db.messages().insert_schedule(1, 3000, b"late").unwrap();
+        let early = db.messages().insert_schedule(1, 1000, b"early").unwrap();
+        let other = db.messages().insert_schedule(2, 2000, b"other").unwrap();
+        let due = db.messages().fetch_due_schedules(2000, 10).unwrap();
+        assert_eq!(
+            due.iter().map(|s| s.id).collect::<Vec<i64>>(),
+            vec![early, other]
+        );
+        assert_eq!(db.messages().fetch_user_schedules(1).unwrap().len(), 2);
+
+        let schedule = db.messages().get_schedule(late).unwrap().unwrap();
+        assert!(db
+            .messages()
+            .update_schedule(&schedule, 500, b"now")
+            .unwrap());
+        assert!(!db
+            .messages()
+            .update_schedule(&schedule, 600, b"stale")
+            .unwrap());
+        let due = db.messages().fetch_due_schedules(1000, 10).unwrap();
+        assert_eq!(due[0].id, late);
+        assert_eq!(due[0].data, b"now");
+
+        assert!(db.messages().remove_schedule(late).unwrap().is_some());
+        assert!(db.messages().remove_schedule(late).unwrap().is_none());
+        assert_eq!(db.messages().fetch_user_schedules(1).unwrap().len(), 1);
+        assert_eq!(
+            db.messages().fetch_due_schedules(1000, 10).unwrap().len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::BTreeSet;
+
+use sled::Batch;
+
+use crate::{Conversation, Messages, Result};
+
+impl<'a> Messages<'a> {
+    /// 为消息文本建立倒排索引
+    pub fn index_msg(&self, conversation: Conversation, mid: i64, text: &str) -> Result<()> {
+        let mut batch = Batch::default();
+        for token in tokenize(text) {
+            batch.insert(key_index(conversation, &token, mid), []);
+        }
+        self.db.db.apply_batch(batch)?;
+        Ok(())
+    }
+
+    /// 删除消息的倒排索引，text为建立索引时的文本
+    pub fn unindex_msg(&self, conversation: Conversation, mid: i64, text: &str) -> Result<()> {
+        let mut batch = Batch::default();
+        for token in tokenize(text) {
+            batch.remove(key_index(conversation, &token, mid));
+        }
+        self.db.db.apply_batch(batch)?;
+        Ok(())
+    }
+
+    /// 在会话中搜索包含query中所有词的消息，按消息id降序返回before之前的最多limit条
+    pub fn search(
+        &self,
+        conversation: Conversation,
+        query: &str,
+        before: Option<i64>,
+        limit: usize,
+    ) -> Result<Vec<i64>> {
+        let mut tokens = query_tokens(query);
+        // 以最长的词驱动扫描，其余的词逐条校验
+        tokens.sort_by_key(|token| std::cmp::Reverse(token.chars().count()));
+        let Some((first, rest)) = tokens.split_first() else {
+            return Ok(vec![]);
+        };
+        let before_id = before.unwrap_or(i64::MAX);
+        let iter = self
+            .db
+            .db
+            .range(key_index(conversation, first, 0)..key_index(conversation, first, before_id))
+            .rev();
+        let mut result = Vec::new();
+
+        for item in iter {
+            if result.len() >= limit {
+                break;
+            }
+            let (key, _) = item?;
+            let mid = i64::from_be_bytes(key[key.len() - 8..].try_into().unwrap());
+            let mut matched = true;
+            for token in rest {
+                if !self
+                    .db
+                    .db
+                    .contains_key(key_index(conversation, token, mid))?
+                {
+                    matched = false;
+                    break;
+                }
+            }
+            if matched {
+                result.push(mid);
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// 倒排索引按会话划分，搜索只扫描所在会话的记录
+fn key_index(conversation: Conversation, token: &str, msg_id: i64) -> Vec<u8> {
+    let mut data = Vec::with_capacity(4 + 17 + token.len() + 9);
+    data.extend_from_slice(b"IDX/");
+    data.extend_from_slice(&conversation.to_bytes());
+    data.extend_from_slice(token.as_bytes());
+    data.push(0);
+    data.extend_from_slice(&msg_id.to_be_bytes());
+    data
+}
+
+/// 中日韩文字没有空格分词，按单字和相邻两字索引
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}'
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{ac00}'..='\u{d7af}'
+        | '\u{f900}'..='\u{faff}'
+        | '\u{20000}'..='\u{2fa1f}')
+}
+
+/// 将文本切分为连续的中日韩文字片段与单词，统一转为小写
+fn segments(text: &str) -> Vec<(bool, Vec<char>)> {
+    let mut segments: Vec<(bool, Vec<char>)> = Vec::new();
+    let mut current: Option<(bool, Vec<char>)> = None;
+    for c in text.chars().flat_map(char::to_lowercase) {
+        let cjk = is_cjk(c);
+        if !cjk && !c.is_alphanumeric() {
+            segments.extend(current.take());
+            continue;
+        }
+        match &mut current {
+            Some((current_cjk, chars)) if *current_cjk == cjk => chars.push(c),
+            _ => {
+                segments.extend(current.take());
+                current = Some((cjk, vec![c]));
+            }
+        }
+    }
+    segments.extend(current);
+    segments
+}
+
+/// 索引的词：单词整体，中日韩文字的单字与相邻两字
+pub fn tokenize(text: &str) -> BTreeSet<String> {
+    let mut tokens = BTreeSet::new();
+    for (cjk, chars) in segments(text) {
+        if cjk {
+            tokens.extend(chars.iter().map(char::to_string));
+            tokens.extend(chars.windows(2).map(|pair| pair.iter().collect()));
+        } else {
+            tokens.insert(chars.into_iter().collect());
+        }
+    }
+    tokens
+}
+
+/// 查询的词：单词整体，中日韩文字两字以上时只用相邻两字，更有区分度
+fn query_tokens(query: &str) -> Vec<String> {
+    let mut tokens = BTreeSet::new();
+    for (cjk, chars) in segments(query) {
+        if cjk && chars.len() > 1 {
+            tokens.extend(chars.windows(2).map(|pair| pair.iter().collect::<String>()));
+        } else {
+            tokens.insert(chars.into_iter().collect::<String>());
+        }
+    }
+    tokens.into_iter().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::search::tokenize;
+    use crate::test::open_temp_db;
+    use crate::Conversation;
+
+    #[test]
+    fn tokenize_mixed_text() {
+        let tokens = tokenize("明天Rust聚会, OK?");
+        for token in ["明", "天", "明天", "rust", "聚会", "ok"] {
+            assert!(tokens.contains(token), "{token}");
+        }
+        assert!(!tokens.contains("天聚"));
+    }
+
+    #[test]
+    fn search_in_conversation() {
+        let (_dir, db) = open_temp_db();
+        let texts = ["明天一起吃饭", "今天吃什么", "Rust 明天发布", "明天见"];
+        let mids = texts
+            .iter()
+            .map(|text| {
+                let mid = db
+                    .messages()
+                    .send_to_group(1, [1, 2], text.as_bytes())
+                    .unwrap();
+                db.messages()
+                    .index_msg(Conversation::Group(1), mid, text)
+                    .unwrap();
+                mid
+            })
+            .collect::<Vec<i64>>();
+        let dm = db
+            .messages()
+            .send_to_dm(1, 2, "明天吃饭".as_bytes())
+            .unwrap();
+        db.messages()
+            .index_msg(Conversation::dm(2, 1), dm, "明天吃饭")
+            .unwrap();
+
+        let found = |query: &str, conversation: Conversation| {
+            db.messages().search(conversation, query, None, 10).unwrap()
+        };
+        assert_eq!(
+            found("明天", Conversation::Group(1)),
+            vec![mids[3], mids[2], mids[0]]
+        );
+        assert_eq!(found("吃饭 明天", Conversation::Group(1)), vec![mids[0]]);
+        assert_eq!(found("RUST", Conversation::Group(1)), vec![mids[2]]);
+        assert_eq!(found("明天", Conversation::dm(1, 2)), vec![dm]);
+
+        db.messages()
+            .unindex_msg(Conversation::Group(1), mids[3], texts[3])
+            .unwrap();
+        let page = db
+            .messages()
+            .search(Conversation::Group(1), "明天", Some(mids[2]), 10)
+            .unwrap();
+        assert_eq!(page, vec![mids[0]]);
+        assert_eq!(
+            found("明天", Conversation::Group(1)),
+            vec![mids[2], mids[0]]
+        );
+    }
+}
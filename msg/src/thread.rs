@@ -0,0 +1,259 @@
+use sled::Batch;
+
+use crate::messages::{key_msg, key_user_msg};
+use crate::{Error, Messages, Result};
+
+/// 话题的统计信息，保存在话题的根消息下
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadStat {
+    /// 回复数量
+    pub count: i64,
+    /// 最后一条回复的消息id
+    pub last_reply: i64,
+    /// 最后一条回复的时间，毫秒时间戳
+    pub last_reply_at: i64,
+}
+
+impl ThreadStat {
+    fn to_bytes(self) -> [u8; 24] {
+        let mut data = [0; 24];
+        data[0..8].copy_from_slice(&self.count.to_be_bytes());
+        data[8..16].copy_from_slice(&self.last_reply.to_be_bytes());
+        data[16..24].copy_from_slice(&self.last_reply_at.to_be_bytes());
+        data
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Self> {
+        if data.len() != 24 {
+            return Err(Error::InvalidData);
+        }
+        Ok(ThreadStat {
+            count: i64::from_be_bytes(data[0..8].try_into().unwrap()),
+            last_reply: i64::from_be_bytes(data[8..16].try_into().unwrap()),
+            last_reply_at: i64::from_be_bytes(data[16..24].try_into().unwrap()),
+        })
+    }
+}
+
+impl<'a> Messages<'a> {
+    /// 发送话题回复，回复不进入群的消息时间线，只保存在根消息的话题下，返回消息id及更新后的话题统计
+    pub fn send_to_thread(
+        &self,
+        root: i64,
+        to: impl IntoIterator<Item = i64>,
+        msg: &[u8],
+        replied_at: i64,
+    ) -> Result<(i64, ThreadStat)> {
+        let id = self.db.generate_msg_id()?;
+        let mut batch = Batch::default();
+        batch.insert(key_msg(id), msg);
+        for target_uid in to {
+            batch.insert(key_user_msg(target_uid, id), msg);
+        }
+        batch.insert(key_thread_msg(root, id), msg);
+        self.db.db.apply_batch(batch)?;
+        let stat = self
+            .db
+            .db
+            .update_and_fetch(key_thread_stat(root), |data| {
+                let count = data
+                    .and_then(|data| ThreadStat::from_bytes(data).ok())
+                    .map_or(0, |stat| stat.count);
+                let stat = ThreadStat {
+                    count: count + 1,
+                    last_reply: id,
+                    last_reply_at: replied_at,
+                };
+                Some(stat.to_bytes().to_vec())
+            })?
+            .ok_or(Error::InvalidData)?;
+        Ok((id, ThreadStat::from_bytes(&stat)?))
+    }
+
+    /// 覆盖话题回复（撤回、编辑等场景），消息id不变，只覆盖发送时已收到该消息的用户
+    pub fn replace_thread_msg(
+        &self,
+        root: i64,
+        to: impl IntoIterator<Item = i64>,
+        mid: i64,
+        msg: &[u8],
+    ) -> Result<()> {
+        let mut batch = Batch::default();
+        batch.insert(key_msg(mid), msg);
+        for target_uid in to {
+            let key = key_user_msg(target_uid, mid);
+            if self.db.db.contains_key(key)? {
+                batch.insert(key, msg);
+            }
+        }
+        batch.insert(key_thread_msg(root, mid), msg);
+        self.db.db.apply_batch(batch)?;
+        Ok(())
+    }
+
+    /// 获取话题的统计信息，没有回复时返回None
+    pub fn get_thread_stat(&self, root: i64) -> Result<Option<ThreadStat>> {
+        self.db
+            .db
+            .get(key_thread_stat(root))?
+            .map(|data| ThreadStat::from_bytes(&data))
+            .transpose()
+    }
+
+    /// 获取话题回复，before之前的limit条消息，按消息id升序返回
+    pub fn fetch_thread_messages_before(
+        &self,
+        root: i64,
+        before: Option<i64>,
+        limit: usize,
+    ) -> Result<Vec<(i64, Vec<u8>)>> {
+        let before_id = before.unwrap_or(i64::MAX);
+        let iter = self
+            .db
+            .db
+            .range(key_thread_msg(root, 0)..key_thread_msg(root, before_id))
+            .rev()
+            .take(limit);
+        let mut msgs = Vec::new();
+        for item in iter {
+            let (key, value) = item?;
+            msgs.push((decode_key_thread_msg(&key)?, value.to_vec()));
+        }
+        msgs.reverse();
+        Ok(msgs)
+    }
+
+    /// 获取话题回复，after之后的limit条消息，按消息id升序返回
+    pub fn fetch_thread_messages_after(
+        &self,
+        root: i64,
+        after: i64,
+        limit: usize,
+    ) -> Result<Vec<(i64, Vec<u8>)>> {
+        let Some(after_id) = after.checked_add(1) else {
+            return Ok(vec![]);
+        };
+        let iter = self
+            .db
+            .db
+            .range(key_thread_msg(root, after_id)..key_thread_msg(root, i64::MAX))
+            .take(limit);
+        let mut msgs = Vec::new();
+        for item in iter {
+            let (key, value) = item?;
+            msgs.push((decode_key_thread_msg(&key)?, value.to_vec()));
+        }
+        Ok(msgs)
+    }
+
+    /// 订阅话题，订阅者会收到话题的新回复
+    pub fn subscribe_thread(&self, root: i64, uid: i64) -> Result<()> {
+        self.db.db.insert(key_thread_subscriber(root, uid), [])?;
+        Ok(())
+    }
+
+    /// 取消订阅话题
+    pub fn unsubscribe_thread(&self, root: i64, uid: i64) -> Result<()> {
+        self.db.db.remove(key_thread_subscriber(root, uid))?;
+        Ok(())
+    }
+
+    /// 获取话题的所有订阅者
+    pub fn fetch_thread_subscribers(&self, root: i64) -> Result<Vec<i64>> {
+        let mut uids = Vec::new();
+        for item in self.db.db.scan_prefix(key_thread_subscriber_prefix(root)) {
+            let (key, _) = item?;
+            uids.push(i64::from_be_bytes(key[13..21].try_into().unwrap()));
+        }
+        Ok(uids)
+    }
+}
+
+pub(crate) fn key_thread_msg(root: i64, msg_id: i64) -> [u8; 21] {
+    let mut data = [0; 21];
+    data[0..5].copy_from_slice(b"TMSG/");
+    data[5..13].copy_from_slice(&root.to_be_bytes());
+    data[13..21].copy_from_slice(&msg_id.to_be_bytes());
+    data
+}
+
+fn decode_key_thread_msg(data: &[u8]) -> Result<i64> {
+    let data = data.strip_prefix(b"TMSG/").ok_or(Error::InvalidData)?;
+    if data.len() != 16 {
+        return Err(Error::InvalidData);
+    }
+    Ok(i64::from_be_bytes(data[8..16].try_into().unwrap()))
+}
+
+pub(crate) fn key_thread_stat(root: i64) -> [u8; 13] {
+    let mut data = [0; 13];
+    data[0..5].copy_from_slice(b"TSTA/");
+    data[5..13].copy_from_slice(&root.to_be_bytes());
+    data
+}
+
+pub(crate) fn key_thread_subscriber_prefix(root: i64) -> [u8; 13] {
+    let mut data = [0; 13];
+    data[0..5].copy_from_slice(b"TSUB/");
+    data[5..13].copy_from_slice(&root.to_be_bytes());
+    data
+}
+
+fn key_thread_subscriber(root: i64, uid: i64) -> [u8; 21] {
+    let mut data = [0; 21];
+    data[0..13].copy_from_slice(&key_thread_subscriber_prefix(root));
+    data[13..21].copy_from_slice(&uid.to_be_bytes());
+    data
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test::open_temp_db;
+
+    #[test]
+    fn thread_replies_out_of_timeline() {
+        let (_dir, db) = open_temp_db();
+        let root = db.messages().send_to_group(1, [1, 2], b"root").unwrap();
+        let replies = (0..3)
+            .map(|i| {
+                let (mid, stat) = db
+                    .messages()
+                    .send_to_thread(root, [1, 2], b"reply", 1000 + i)
+                    .unwrap();
+                assert_eq!(stat.count, i + 1);
+                assert_eq!(stat.last_reply, mid);
+                mid
+            })
+            .collect::<Vec<i64>>();
+        let timeline = db
+            .messages()
+            .fetch_group_messages_before(1, None, 10)
+            .unwrap();
+        assert_eq!(timeline.len(), 1);
+        assert!(db.messages().is_received(2, replies[0]).unwrap());
+
+        let page = db
+            .messages()
+            .fetch_thread_messages_before(root, Some(replies[2]), 10)
+            .unwrap();
+        assert_eq!(
+            page.iter().map(|(mid, _)| *mid).collect::<Vec<i64>>(),
+            replies[0..2]
+        );
+        let stat = db.messages().get_thread_stat(root).unwrap().unwrap();
+        assert_eq!((stat.count, stat.last_reply_at), (3, 1002));
+        assert!(db.messages().get_thread_stat(replies[0]).unwrap().is_none());
+
+        db.messages().subscribe_thread(root, 2).unwrap();
+        db.messages().subscribe_thread(root, 1).unwrap();
+        assert_eq!(
+            db.messages().fetch_thread_subscribers(root).unwrap(),
+            vec![1, 2]
+        );
+        db.messages().unsubscribe_thread(root, 1).unwrap();
+        assert_eq!(
+            db.messages().fetch_thread_subscribers(root).unwrap(),
+            vec![2]
+        );
+    }
+}
@@ -1,12 +1,15 @@
 use crate::app_state::AppState;
 use crate::auth::Token;
-use crate::{middleware, Api, Res};
-use axum::extract::State;
+use crate::datetime::datetime_format;
+use crate::{message, middleware, Api, Res};
+use axum::extract::{Path, State};
 use axum::routing::get;
 use axum::{Json, Router};
+use chrono::{DateTime, Local};
 use entity::prelude::User;
 use entity::user::Model;
 use sea_orm::EntityTrait;
+use serde::Serialize;
 
 pub struct AdminApi;
 
@@ -14,6 +17,8 @@ impl Api for AdminApi {
     fn route(app_state: AppState) -> Router {
         Router::new()
             .nest("/user", Router::new().route("/", get(all)))
+            .route("/online", get(online))
+            .route("/msg/:mid/revision", get(revisions))
             .route_layer(axum::middleware::from_fn_with_state(
                 app_state.clone(),
                 middleware::check_admin,
@@ -25,3 +30,46 @@ impl Api for AdminApi {
 async fn all(State(app_state): State<AppState>, _: Token) -> Res<Json<Vec<Model>>> {
     Ok(Json(User::find().all(&app_state.db).await?))
 }
+
+/// 在线统计
+#[derive(Serialize)]
+struct OnlineRes {
+    /// 在线用户数
+    users: usize,
+    /// 在线连接数
+    connections: usize,
+}
+
+async fn online(State(app_state): State<AppState>, _: Token) -> Res<Json<OnlineRes>> {
+    Ok(Json(OnlineRes {
+        users: app_state.hub.online_users(),
+        connections: app_state.hub.online_connections(),
+    }))
+}
+
+/// 消息的历史版本
+#[derive(Serialize)]
+struct RevisionRes {
+    /// 消息内容
+    msg: String,
+    /// 该版本的生成时间，首个版本为发送时间
+    #[serde(with = "datetime_format")]
+    time: DateTime<Local>,
+}
+
+/// 查看消息的编辑记录
+async fn revisions(
+    State(app_state): State<AppState>,
+    Path(mid): Path<i64>,
+    _: Token,
+) -> Res<Json<Vec<RevisionRes>>> {
+    Ok(Json(
+        message::get_revisions(mid, &app_state)?
+            .into_iter()
+            .map(|payload| RevisionRes {
+                msg: payload.detail.get_content(),
+                time: payload.edited_at.unwrap_or(payload.created_at),
+            })
+            .collect(),
+    ))
+}
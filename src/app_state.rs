@@ -4,18 +4,21 @@ use std::path::PathBuf;
 use std::sync::{Arc, LazyLock, Mutex};
 
 use sea_orm::{Database, DatabaseConnection};
-use tokio::sync::broadcast;
 
 use msg::MsgDb;
 
 use crate::err::ServerError;
-use crate::event::BroadcastEvent;
+use crate::event::Hub;
+use crate::presence::Activities;
+use crate::typing::TypingThrottle;
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: DatabaseConnection,
     pub msg_db: Arc<Mutex<MsgDb>>,
-    pub event_sender: Arc<broadcast::Sender<Arc<BroadcastEvent>>>,
+    pub hub: Arc<Hub>,
+    pub activities: Arc<Activities>,
+    pub typing: Arc<TypingThrottle>,
 }
 
 static ENVS: LazyLock<HashMap<String, String>> = LazyLock::new(|| {
@@ -42,11 +45,12 @@ impl AppState {
         }
         let db = Database::connect("sqlite://data/db/chat.sqlite?mode=rwc").await.expect("fail to connect to sqlite db");
 
-        let (sender, _) = broadcast::channel(128);
         Ok(AppState {
             db,
             msg_db: Arc::new(Mutex::new(msg_db)),
-            event_sender: Arc::new(sender),
+            hub: Arc::new(Hub::default()),
+            activities: Arc::new(Activities::default()),
+            typing: Arc::new(TypingThrottle::default()),
         })
     }
 }
@@ -0,0 +1,505 @@
+use std::collections::HashSet;
+use std::env;
+use std::path::PathBuf;
+use std::sync::LazyLock;
+
+use axum::extract::{DefaultBodyLimit, Multipart, Path, State};
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use bytes::Bytes;
+use entity::attachment;
+use entity::prelude::Attachment;
+use image::imageops::FilterType;
+use image::ImageFormat;
+use msg::Conversation;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ActiveModelTrait, ColumnTrait, EntityTrait, NotSet, QueryFilter};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio::fs;
+use utoipa::ToSchema;
+
+use crate::app_state::AppState;
+use crate::auth::Token;
+use crate::err::{ErrPrint, ServerError};
+use crate::message::{ImageContent, Thumbnail};
+use crate::user::UserErr;
+use crate::{friend, group, message, middleware, user, Api, Res};
+
+/// 附件存储目录，文件按内容的sha256存放，相同内容只保存一份
+const ATTACHMENT_DIR: &str = "data/attachments";
+
+/// 图片缩略图的尺寸名称与最长边像素数，按尺寸从小到大排列
+const THUMBNAIL_SIZES: [(&str, u32); 2] = [("small", 240), ("medium", 960)];
+
+/// 附件大小上限，默认20MB
+static MAX_SIZE: LazyLock<usize> = LazyLock::new(|| {
+    env::var("ATTACHMENT_MAX_SIZE")
+        .ok()
+        .and_then(|size| size.parse().ok())
+        .unwrap_or(20 * 1024 * 1024)
+});
+
+/// 允许上传的MIME类型，逗号分隔，以/结尾表示匹配该大类下的所有类型
+static ALLOWED_MIME: LazyLock<Vec<String>> = LazyLock::new(|| {
+    env::var("ATTACHMENT_ALLOWED_MIME")
+        .unwrap_or_else(|_| {
+            "image/,audio/,video/,text/plain,application/pdf,application/zip".to_string()
+        })
+        .split(',')
+        .map(|mime| mime.trim().to_string())
+        .filter(|mime| !mime.is_empty())
+        .collect()
+});
+
+pub struct AttachmentApi;
+
+impl Api for AttachmentApi {
+    fn route(app_state: AppState) -> Router {
+        Router::new()
+            .route("/", post(upload))
+            .route("/:id", get(download))
+            .route("/:id/thumbnail/:size", get(thumbnail))
+            // multipart的边界等额外开销预留1MB
+            .layer(DefaultBodyLimit::max(*MAX_SIZE + 1024 * 1024))
+            .route_layer(axum::middleware::from_fn_with_state(
+                app_state.clone(),
+                middleware::check_user_status,
+            ))
+            .route_layer(axum::middleware::from_fn_with_state(
+                app_state.clone(),
+                middleware::check_login,
+            ))
+            .with_state(app_state.clone())
+    }
+}
+
+/// 附件相关错误
+#[derive(Debug, Error, ToSchema)]
+pub enum AttachmentErr {
+    /// 附件不存在
+    #[error("附件{0}不存在")]
+    AttachmentNotExist(i64),
+    /// 请求中没有文件
+    #[error("请选择要上传的文件")]
+    MissingFile,
+    /// 文件过大
+    #[error("文件大小不能超过{0}字节")]
+    TooLarge(usize),
+    /// 不支持的文件类型
+    #[error("不支持上传{0}类型的文件")]
+    MimeNotAllowed(String),
+    /// 无权访问该附件
+    #[error("您无权访问该附件")]
+    NoPermission,
+    /// 缩略图不存在
+    #[error("附件{0}没有{1}尺寸的缩略图")]
+    ThumbnailNotExist(i64, String),
+}
+
+impl ErrPrint for AttachmentErr {}
+
+/// 上传结果
+#[derive(Serialize, ToSchema)]
+pub struct AttachmentRes {
+    /// 附件id，发送消息时填入消息内容
+    pub id: i64,
+    /// 下载地址
+    pub url: String,
+    pub name: String,
+    pub mime: String,
+    /// 文件大小，单位字节
+    pub size: i64,
+    /// 图片宽度，非图片为空
+    pub width: Option<i32>,
+    /// 图片高度，非图片为空
+    pub height: Option<i32>,
+    /// 图片缩略图
+    pub thumbnails: Vec<Thumbnail>,
+}
+
+impl From<attachment::Model> for AttachmentRes {
+    fn from(model: attachment::Model) -> Self {
+        AttachmentRes {
+            url: url_of(model.id),
+            thumbnails: thumbnails_of(&model),
+            id: model.id,
+            name: model.name,
+            mime: model.mime,
+            size: model.size,
+            width: model.width,
+            height: model.height,
+        }
+    }
+}
+
+/// 附件的下载地址
+pub(crate) fn url_of(id: i64) -> String {
+    format!("/attachment/{id}")
+}
+
+/// 图片附件的缩略图，尺寸与生成缩略图时一致
+fn thumbnails_of(model: &attachment::Model) -> Vec<Thumbnail> {
+    let (Some(width), Some(height)) = (model.width, model.height) else {
+        return vec![];
+    };
+    THUMBNAIL_SIZES
+        .iter()
+        .map(|&(size, max)| {
+            let (width, height) = fit(width as u32, height as u32, max);
+            Thumbnail {
+                size: size.to_string(),
+                url: format!("{}/thumbnail/{size}", url_of(model.id)),
+                width,
+                height,
+            }
+        })
+        .collect()
+}
+
+/// 根据附件信息填充图片消息的地址、尺寸与缩略图
+pub(crate) fn fill_image(image: &mut ImageContent, model: &attachment::Model) {
+    image.url = url_of(model.id);
+    image.width = model.width.map(|width| width as u32);
+    image.height = model.height.map(|height| height as u32);
+    image.thumbnails = thumbnails_of(model);
+}
+
+/// 按最长边不超过max等比缩放，不放大
+fn fit(width: u32, height: u32, max: u32) -> (u32, u32) {
+    let longest = width.max(height);
+    if longest <= max {
+        return (width, height);
+    }
+    let scale = |edge: u32| ((edge as u64 * max as u64 / longest as u64) as u32).max(1);
+    (scale(width), scale(height))
+}
+
+/// 上传附件，表单字段名为file
+async fn upload(
+    State(app_state): State<AppState>,
+    token: Token,
+    mut multipart: Multipart,
+) -> Res<Json<AttachmentRes>> {
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|err| ServerError::CustomErr(err.to_string()))?
+    {
+        if field.name() != Some("file") {
+            continue;
+        }
+        let name = field.file_name().unwrap_or("unnamed").to_string();
+        let mime = field
+            .content_type()
+            .unwrap_or("application/octet-stream")
+            .to_string();
+        if !is_allowed(&mime) {
+            return Err(AttachmentErr::MimeNotAllowed(mime).into());
+        }
+        let data = field.bytes().await.map_err(|err| match err.status() {
+            StatusCode::PAYLOAD_TOO_LARGE => AttachmentErr::TooLarge(*MAX_SIZE).into(),
+            _ => ServerError::CustomErr(err.to_string()),
+        })?;
+        if data.len() > *MAX_SIZE {
+            return Err(AttachmentErr::TooLarge(*MAX_SIZE).into());
+        }
+        let size = data.len() as i64;
+        let hash = format!("{:x}", Sha256::digest(&data));
+        store(&hash, &data).await?;
+        // 不信任客户端声明的类型，图片记录解码得到的格式与尺寸并生成缩略图，其他文件按内容识别类型
+        let (mime, width, height) = match process_image(hash.clone(), data.clone()).await? {
+            Some((mime, width, height)) => (mime, Some(width as i32), Some(height as i32)),
+            None => (sniff(&mime, &data).to_string(), None, None),
+        };
+        let model = attachment::ActiveModel {
+            id: NotSet,
+            hash: Set(hash),
+            name: Set(name),
+            mime: Set(mime),
+            size: Set(size),
+            uploader: Set(token.id),
+            create_time: NotSet,
+            width: Set(width),
+            height: Set(height),
+        }
+        .insert(&app_state.db)
+        .await?;
+        return Ok(Json(model.into()));
+    }
+    Err(AttachmentErr::MissingFile.into())
+}
+
+/// 下载附件，仅上传者与收到引用了该附件的消息的用户可以下载
+async fn download(
+    State(app_state): State<AppState>,
+    Path(id): Path<i64>,
+    token: Token,
+) -> Res<impl IntoResponse> {
+    let model = get_by_id(id, &app_state).await?;
+    if !can_access(&model, token.id, &app_state).await? {
+        return Err(AttachmentErr::NoPermission.into());
+    }
+    let data = fs::read(path_of(&model.hash)).await?;
+    // 只有解码成功的位图可以在浏览器中直接打开，其他文件一律作为下载，避免同源执行脚本
+    let disposition = if model.width.is_some() {
+        "inline"
+    } else {
+        "attachment"
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, model.mime),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "{disposition}; filename=\"{}\"",
+                    model.name.replace(['"', '\r', '\n'], "")
+                ),
+            ),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        data,
+    ))
+}
+
+/// 下载图片附件的缩略图
+async fn thumbnail(
+    State(app_state): State<AppState>,
+    Path((id, size)): Path<(i64, String)>,
+    token: Token,
+) -> Res<impl IntoResponse> {
+    let model = get_by_id(id, &app_state).await?;
+    if !can_access(&model, token.id, &app_state).await? {
+        return Err(AttachmentErr::NoPermission.into());
+    }
+    if model.width.is_none() || !THUMBNAIL_SIZES.iter().any(|&(name, _)| name == size) {
+        return Err(AttachmentErr::ThumbnailNotExist(id, size).into());
+    }
+    let data = fs::read(thumbnail_path_of(&model.hash, &size)).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/jpeg"),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        ],
+        data,
+    ))
+}
+
+pub(crate) async fn get_by_id(
+    id: i64,
+    app_state: &AppState,
+) -> Result<attachment::Model, ServerError> {
+    Attachment::find_by_id(id)
+        .one(&app_state.db)
+        .await?
+        .ok_or(AttachmentErr::AttachmentNotExist(id).into())
+}
+
+/// 用户是否可以访问附件：上传者，或者是引用了附件的消息所在会话的成员（好友或群成员）
+pub(crate) async fn can_access(
+    model: &attachment::Model,
+    uid: i32,
+    app_state: &AppState,
+) -> Result<bool, ServerError> {
+    if model.uploader == uid {
+        return Ok(true);
+    }
+    let mids = app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .fetch_attachment_refs(model.id)?;
+    let conversations = mids
+        .into_iter()
+        .filter_map(|mid| message::get_by_mid(mid, app_state))
+        .map(|message| message.payload.conversation())
+        .collect::<HashSet<Conversation>>();
+    let mut dgraph_uid = None;
+    for conversation in conversations {
+        let accessible = match conversation {
+            Conversation::Dm(a, b) if a == uid as i64 || b == uid as i64 => {
+                let friend = if a == uid as i64 { b } else { a };
+                if dgraph_uid.is_none() {
+                    dgraph_uid = Some(
+                        user::get_by_id(uid, app_state)
+                            .await?
+                            .ok_or(UserErr::UserNotExist(uid))?
+                            .dgraph_uid,
+                    );
+                }
+                friend::is_friend(dgraph_uid.clone().unwrap_or_default(), friend as i32).await
+            }
+            Conversation::Dm(..) => false,
+            Conversation::Group(gid) => group::in_group(gid as i32, uid, app_state).await?,
+        };
+        if accessible {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// 附件已没有消息引用时删除附件记录，没有其他附件记录使用相同内容时删除文件与缩略图
+pub(crate) async fn remove_unreferenced(id: i64, app_state: &AppState) -> Result<(), ServerError> {
+    let referenced = !app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .fetch_attachment_refs(id)?
+        .is_empty();
+    if referenced {
+        return Ok(());
+    }
+    let Some(model) = Attachment::find_by_id(id).one(&app_state.db).await? else {
+        return Ok(());
+    };
+    Attachment::delete_by_id(id).exec(&app_state.db).await?;
+    let shared = Attachment::find()
+        .filter(attachment::Column::Hash.eq(&model.hash))
+        .one(&app_state.db)
+        .await?
+        .is_some();
+    if shared {
+        return Ok(());
+    }
+    let paths = THUMBNAIL_SIZES
+        .iter()
+        .map(|(size, _)| thumbnail_path_of(&model.hash, size))
+        .chain([path_of(&model.hash)]);
+    for path in paths {
+        if let Err(err) = fs::remove_file(&path).await {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                return Err(err.into());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_allowed(mime: &str) -> bool {
+    ALLOWED_MIME.iter().any(|allowed| {
+        if allowed.ends_with('/') {
+            mime.starts_with(allowed.as_str())
+        } else {
+            mime == allowed
+        }
+    })
+}
+
+/// 可按文件头识别的非图片类型
+const MAGIC_NUMBERS: [(&[u8], &str); 7] = [
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"ID3", "audio/mpeg"),
+    (b"OggS", "audio/ogg"),
+    (b"fLaC", "audio/flac"),
+    (b"\x1a\x45\xdf\xa3", "video/webm"),
+    (b"#!AMR", "audio/amr"),
+];
+
+/// 按文件内容识别非图片文件的类型，客户端声明为纯文本且内容是UTF-8时视为纯文本，无法识别时为application/octet-stream
+fn sniff(declared: &str, data: &[u8]) -> &'static str {
+    if let Some(&(_, mime)) = MAGIC_NUMBERS
+        .iter()
+        .find(|(magic, _)| data.starts_with(magic))
+    {
+        return mime;
+    }
+    match data {
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => "video/mp4",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => "audio/wav",
+        _ if declared == "text/plain" && std::str::from_utf8(data).is_ok() => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+fn path_of(hash: &str) -> PathBuf {
+    PathBuf::from(ATTACHMENT_DIR).join(&hash[0..2]).join(hash)
+}
+
+fn thumbnail_path_of(hash: &str, size: &str) -> PathBuf {
+    path_of(hash).with_extension(format!("{size}.jpg"))
+}
+
+/// 解析图片并生成各尺寸的缩略图，返回图片实际的MIME与宽高，无法解析的图片返回None
+async fn process_image(
+    hash: String,
+    data: Bytes,
+) -> Result<Option<(String, u32, u32)>, ServerError> {
+    tokio::task::spawn_blocking(move || {
+        let Ok(format) = image::guess_format(&data) else {
+            return Ok(None);
+        };
+        let Ok(img) = image::load_from_memory_with_format(&data, format) else {
+            return Ok(None);
+        };
+        for (size, max) in THUMBNAIL_SIZES {
+            let path = thumbnail_path_of(&hash, size);
+            if path.exists() {
+                continue;
+            }
+            let (width, height) = fit(img.width(), img.height(), max);
+            let thumbnail = img
+                .resize_exact(width, height, FilterType::Triangle)
+                .into_rgb8();
+            let tmp = path.with_extension(format!("{}.tmp", fastrand::u64(..)));
+            thumbnail
+                .save_with_format(&tmp, ImageFormat::Jpeg)
+                .map_err(|err| ServerError::CustomErr(err.to_string()))?;
+            std::fs::rename(&tmp, &path)?;
+        }
+        Ok(Some((
+            format.to_mime_type().to_string(),
+            img.width(),
+            img.height(),
+        )))
+    })
+    .await
+    .map_err(|err| ServerError::CustomErr(err.to_string()))?
+}
+
+/// 保存文件，相同内容的文件已存在时直接复用
+async fn store(hash: &str, data: &[u8]) -> Result<(), ServerError> {
+    let path = path_of(hash);
+    if fs::try_exists(&path).await? {
+        return Ok(());
+    }
+    let dir = path.parent().unwrap();
+    fs::create_dir_all(dir).await?;
+    // 先写入临时文件再重命名，避免并发上传相同文件时读到不完整的内容
+    let tmp = dir.join(format!("{hash}.{}.tmp", fastrand::u64(..)));
+    fs::write(&tmp, data).await?;
+    fs::rename(&tmp, &path).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::attachment::{fit, sniff};
+
+    #[test]
+    fn fit_longest_edge() {
+        assert_eq!(fit(4000, 3000, 960), (960, 720));
+        assert_eq!(fit(300, 6000, 240), (12, 240));
+        assert_eq!(fit(100, 80, 240), (100, 80));
+        assert_eq!(fit(10000, 1, 240), (240, 1));
+    }
+
+    #[test]
+    fn sniff_ignores_declared_type() {
+        let svg = b"<svg xmlns=\"http://www.w3.org/2000/svg\"><script/></svg>";
+        assert_eq!(sniff("image/svg+xml", svg), "application/octet-stream");
+        assert_eq!(
+            sniff("text/html", b"<html></html>"),
+            "application/octet-stream"
+        );
+        assert_eq!(sniff("text/plain", b"hello"), "text/plain");
+        assert_eq!(sniff("image/png", b"%PDF-1.7"), "application/pdf");
+    }
+}
@@ -9,9 +9,12 @@ use tracing::{error, warn};
 use utoipa::ToSchema;
 use validator::ValidationErrors;
 
+use crate::attachment::AttachmentErr;
 use crate::auth::AuthError;
 use crate::friend::FriendErr;
 use crate::group::GroupErr;
+use crate::message::MessageErr;
+use crate::schedule::ScheduleErr;
 use crate::user::UserErr;
 use crate::{friend, AppRes};
 
@@ -39,6 +42,12 @@ pub enum ServerError {
     ReqwestErr(#[from] reqwest::Error),
     #[error(transparent)]
     FriendErr(#[from] FriendErr),
+    #[error(transparent)]
+    MessageErr(#[from] MessageErr),
+    #[error(transparent)]
+    AttachmentErr(#[from] AttachmentErr),
+    #[error(transparent)]
+    ScheduleErr(#[from] ScheduleErr),
 }
 
 const ERROR_MESSAGE: &str = "系统异常，请稍后再试";
@@ -101,6 +110,9 @@ impl IntoResponse for ServerError {
                         (StatusCode::FORBIDDEN, err.to_string()).into_response()
                     }
                     GroupErr::YouAreForbid => (StatusCode::FORBIDDEN, err.to_string()).into_response(),
+                    GroupErr::MentionAllNotAllowed => {
+                        (StatusCode::FORBIDDEN, err.to_string()).into_response()
+                    }
                 }
             }
             ServerError::AuthErr(err) => {
@@ -160,6 +172,73 @@ impl IntoResponse for ServerError {
                     }
                 }
             }
+            ServerError::MessageErr(err) => {
+                err.print();
+                match err {
+                    MessageErr::MessageNotExist(_) => {
+                        (StatusCode::NOT_FOUND, err.to_string()).into_response()
+                    }
+                    MessageErr::MessageRecalled => {
+                        (StatusCode::GONE, err.to_string()).into_response()
+                    }
+                    MessageErr::RecallTimeout(_) => {
+                        (StatusCode::FORBIDDEN, err.to_string()).into_response()
+                    }
+                    MessageErr::NoPermission => {
+                        (StatusCode::FORBIDDEN, err.to_string()).into_response()
+                    }
+                    MessageErr::SendInProgress(_) => {
+                        (StatusCode::CONFLICT, err.to_string()).into_response()
+                    }
+                    MessageErr::QuoteNotInConversation(_)
+                    | MessageErr::ForwardAcrossConversations
+                    | MessageErr::NotMerged(_)
+                    | MessageErr::NotEditable
+                    | MessageErr::InvalidReaction
+                    | MessageErr::TooManyReactions(_)
+                    | MessageErr::MentionOutsideGroup
+                    | MessageErr::NotThreadable(_)
+                    | MessageErr::TooManyPins(_)
+                    | MessageErr::NotDirectMessage(_)
+                    | MessageErr::NotGroupMessage(_) => {
+                        (StatusCode::BAD_REQUEST, err.to_string()).into_response()
+                    }
+                }
+            }
+            ServerError::AttachmentErr(err) => {
+                err.print();
+                match err {
+                    AttachmentErr::AttachmentNotExist(_) => {
+                        (StatusCode::NOT_FOUND, err.to_string()).into_response()
+                    }
+                    AttachmentErr::MissingFile => {
+                        (StatusCode::BAD_REQUEST, err.to_string()).into_response()
+                    }
+                    AttachmentErr::TooLarge(_) => {
+                        (StatusCode::PAYLOAD_TOO_LARGE, err.to_string()).into_response()
+                    }
+                    AttachmentErr::MimeNotAllowed(_) => {
+                        (StatusCode::UNSUPPORTED_MEDIA_TYPE, err.to_string()).into_response()
+                    }
+                    AttachmentErr::NoPermission => {
+                        (StatusCode::FORBIDDEN, err.to_string()).into_response()
+                    }
+                    AttachmentErr::ThumbnailNotExist(_, _) => {
+                        (StatusCode::NOT_FOUND, err.to_string()).into_response()
+                    }
+                }
+            }
+            ServerError::ScheduleErr(err) => {
+                err.print();
+                match err {
+                    ScheduleErr::ScheduleNotExist(_) => {
+                        (StatusCode::NOT_FOUND, err.to_string()).into_response()
+                    }
+                    ScheduleErr::InvalidSendAt(_) | ScheduleErr::TooManySchedules(_) => {
+                        (StatusCode::BAD_REQUEST, err.to_string()).into_response()
+                    }
+                }
+            }
         }
         .into_response()
     }
@@ -3,27 +3,39 @@ use std::collections::BTreeSet;
 use std::convert::Infallible;
 use std::fmt::{Display, Formatter};
 use std::path::PathBuf;
-use std::sync::Arc;
 use std::time::Duration;
 
 use crate::app_state::AppState;
 use crate::auth::Token;
-use crate::message::ChatMessage;
-use crate::{middleware, Api};
-use axum::extract::State;
+use crate::err::ServerError;
+use crate::message::{
+    ChatMessage, MessageTarget, MessageTargetGroup, ReceiptStatus, SeenCount, SendMsgReq,
+    ThreadSummary,
+};
+use crate::presence::PresenceVo;
+use crate::read_index::UpdateReadIndex;
+use crate::{message, middleware, presence, read_index, typing, user, Api};
+use axum::extract::ws::{WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
 use axum::response::sse::Event;
-use axum::response::Sse;
+use axum::response::{Response, Sse};
 use axum::routing::get;
 use axum::Router;
 use axum_extra::{headers, TypedHeader};
 use chrono::{DateTime, Local};
-use futures::Stream;
-use serde::Serialize;
-use tokio::sync::broadcast::Receiver;
+use futures::{SinkExt, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::time::Instant;
 use tower_http::services::ServeDir;
+use tracing::{debug, warn};
+use validator::Validate;
+
+mod hub;
+
+pub use hub::{Connection, Hub};
 
 pub struct EventApi;
 
@@ -35,6 +47,7 @@ impl Api for EventApi {
         Router::new()
             .fallback_service(static_files_service)
             .route("/stream", get(event_handler))
+            .route("/ws", get(ws_handler))
             .route_layer(axum::middleware::from_fn_with_state(
                 app_state.clone(),
                 middleware::check_login,
@@ -43,12 +56,29 @@ impl Api for EventApi {
     }
 }
 
+/// 断线重连时的续传参数
+#[derive(Deserialize)]
+struct ResumeParams {
+    /// 客户端最后收到的消息id，EventSource无法自定义header时通过query传递
+    last_event_id: Option<i64>,
+}
+
+const LAST_EVENT_ID: &str = "Last-Event-ID";
+
 async fn event_handler(
     State(app_state): State<AppState>,
     token: Token, // sse无法通过header传递，需要通过query传递，需提供一个从query解析的QueryToken同该接口使用
     TypedHeader(user_agent): TypedHeader<headers::UserAgent>,
+    headers: HeaderMap,
+    Query(resume): Query<ResumeParams>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
     println!("`{}` connected", user_agent.as_str());
+    // EventSource重连时会自动携带Last-Event-ID header，优先使用header
+    let last_mid = headers
+        .get(LAST_EVENT_ID)
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.parse::<i64>().ok())
+        .or(resume.last_event_id);
 
     // You can also create streams from tokio channels using the wrappers in
     // https://docs.rs/tokio-stream
@@ -56,9 +86,12 @@ async fn event_handler(
     tokio::spawn(event_loop(
         tx_msg,
         token.id,
-        app_state.event_sender.subscribe(),
-    )); // 临时使用1
-    let receiver_stream = tokio_stream::wrappers::UnboundedReceiverStream::from(rx_msg);
+        app_state.hub.register(token.id),
+        app_state.clone(),
+        last_mid,
+    ));
+    let receiver_stream = tokio_stream::wrappers::UnboundedReceiverStream::from(rx_msg)
+        .map(|message| Ok(message.into_event()));
     Sse::new(receiver_stream).keep_alive(
         axum::response::sse::KeepAlive::new()
             .interval(Duration::from_secs(5))
@@ -66,44 +99,352 @@ async fn event_handler(
     )
 }
 
+/// websocket连接，与sse推送相同的消息，同时接收客户端上行的指令
+async fn ws_handler(
+    State(app_state): State<AppState>,
+    // 浏览器websocket同样无法设置header，token通过query传递
+    token: Token,
+    Query(resume): Query<ResumeParams>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.on_upgrade(move |socket| ws_loop(socket, app_state, token, resume.last_event_id))
+}
+
+async fn ws_loop(socket: WebSocket, app_state: AppState, token: Token, last_mid: Option<i64>) {
+    let (mut sink, mut stream) = socket.split();
+    let (tx_msg, mut rx_msg) = mpsc::unbounded_channel();
+    tokio::spawn(event_loop(
+        tx_msg.clone(),
+        token.id,
+        app_state.hub.register(token.id),
+        app_state.clone(),
+        last_mid,
+    ));
+    loop {
+        tokio::select! {
+            message = rx_msg.recv() => {
+                let Some(message) = message else { break };
+                let text = serde_json::to_string(&message).expect("fail to transfer message to json");
+                if sink.send(axum::extract::ws::Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            frame = stream.next() => {
+                match frame {
+                    Some(Ok(axum::extract::ws::Message::Text(text))) => {
+                        presence::active(token.id, &app_state);
+                        let result = match serde_json::from_str::<Command>(&text) {
+                            Ok(command) => handle_command(command, &app_state, &token).await,
+                            Err(err) => Some(CommandResult::invalid(err.to_string())),
+                        };
+                        if let Some(result) = result {
+                            if tx_msg.send(Message::CommandResult(result)).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Some(Ok(axum::extract::ws::Message::Close(_))) | Some(Err(_)) | None => break,
+                    // ping/pong由axum自动处理，二进制消息不处理
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+    }
+    debug!("websocket of user {} closed", token.id);
+}
+
+/// websocket上行指令
+#[derive(Deserialize)]
+enum Command {
+    /// 发送消息，seq由客户端生成，用于匹配返回的CommandResult
+    Send {
+        seq: u64,
+        target: MessageTarget,
+        msg: SendMsgReq,
+    },
+    /// 确认已收到消息
+    Ack { mid: i64 },
+    /// 更新已读位置
+    ReadIndex(UpdateReadIndex),
+    /// 正在输入
+    Typing(MessageTarget),
+}
+
+/// 处理上行指令，需要回复客户端时返回Some
+async fn handle_command(
+    command: Command,
+    app_state: &AppState,
+    token: &Token,
+) -> Option<CommandResult> {
+    match command {
+        Command::Send { seq, target, msg } => {
+            let result = send(target, msg, app_state, token).await;
+            Some(CommandResult::new(seq, result))
+        }
+        Command::Ack { mid } => {
+            debug!("user {} ack message {mid}", token.id);
+            if let Some(message) = message::get_by_mid(mid, app_state) {
+                ack_delivery(app_state, token.id, &message);
+            }
+            None
+        }
+        Command::ReadIndex(update_read_index) => {
+            if let Err(err) =
+                read_index::set_read_index(app_state, token.id, update_read_index).await
+            {
+                warn!("fail to set read index of user {}: {err}", token.id);
+            }
+            None
+        }
+        Command::Typing(target) => {
+            let result = match user::check_status(token.id, token.id, app_state).await {
+                Ok(()) => typing::send(target, app_state, token).await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                debug!("fail to send typing of user {}: {err}", token.id);
+            }
+            None
+        }
+    }
+}
+
+async fn send(
+    target: MessageTarget,
+    msg: SendMsgReq,
+    app_state: &AppState,
+    token: &Token,
+) -> Result<i64, ServerError> {
+    msg.validate()?;
+    // websocket不经过check_user_status，需校验发送者未被冻结
+    user::check_status(token.id, token.id, app_state).await?;
+    message::send_once(target, msg, app_state, token).await
+}
+
+/// 每批补发的消息数量
+const REPLAY_BATCH: usize = 100;
+
 async fn event_loop(
-    tx_msg: UnboundedSender<Result<Event, Infallible>>,
+    tx_msg: UnboundedSender<Message>,
     current_uid: i32,
-    mut receiver: Receiver<Arc<BroadcastEvent>>,
+    connection: Connection,
+    app_state: AppState,
+    last_mid: Option<i64>,
 ) {
+    presence::connected(current_uid, &app_state).await;
+    // connection在推送结束时注销，之后才能判断用户是否已没有其他连接
+    push(&tx_msg, current_uid, connection, &app_state, last_mid).await;
+    presence::disconnected(current_uid, &app_state).await;
+}
+
+async fn push(
+    tx_msg: &UnboundedSender<Message>,
+    current_uid: i32,
+    mut connection: Connection,
+    app_state: &AppState,
+    last_mid: Option<i64>,
+) {
+    // 已注册连接后再补发断线期间的消息，补发期间到达的实时消息按mid去重
+    let mut replayed_mid = match last_mid {
+        None => None,
+        Some(last_mid) => match replay(tx_msg, app_state, current_uid, last_mid) {
+            None => return,
+            replayed_mid => replayed_mid,
+        },
+    };
     let mut heartbeat = tokio::time::interval_at(
         Instant::now() + Duration::from_secs(5),
         Duration::from_secs(60),
     );
     loop {
         tokio::select! {
-            res = receiver.recv() =>{
-                match res {
-                    Ok(event) => {
-                        match &*event{
-                            BroadcastEvent::Chat{ targets,message } => {
-                                if !targets.contains(&current_uid) && message.payload.from_uid != current_uid{
-                                    continue;
-                                }
-                                let chat = Message::ChatMessage(message.clone());
-                                let event = Event::default().event(chat.to_string()).json_data(chat).expect("fail to transfer event to json");
-                                if tx_msg.send(Ok(event)).is_err() {
-                                    break;
-                                }
-                            }
+            event = connection.recv() => {
+                let Some(event) = event else { break };
+                if forward(tx_msg, &event, replayed_mid).is_err() {
+                    break;
+                }
+                if let BroadcastEvent::Chat { message, .. } = &*event {
+                    ack_delivery(app_state, current_uid, message);
+                }
+                if connection.is_lagged() {
+                    // 先消费完积压的事件，再从消息库补发lag期间被丢弃的消息
+                    while let Some(event) = connection.try_recv() {
+                        if forward(tx_msg, &event, replayed_mid).is_err() {
+                            return;
+                        }
+                        if let BroadcastEvent::Chat { message, .. } = &*event {
+                            ack_delivery(app_state, current_uid, message);
+                        }
+                    }
+                    let recovery = connection.recover();
+                    if let Some(dropped_from) = recovery.dropped_from {
+                        match replay(tx_msg, app_state, current_uid, dropped_from - 1) {
+                            None => break,
+                            mid => replayed_mid = replayed_mid.max(mid),
+                        }
+                    }
+                    // 编辑、撤回、回执等事件无法补发，通知客户端重新同步
+                    if recovery.resync {
+                        let resync = Message::Resync(ResyncMessage { time: Local::now() });
+                        if tx_msg.send(resync).is_err() {
+                            break;
                         }
                     }
-                    Err(_) => break,
                 }
             }
             _ = heartbeat.tick() =>{
                 let heartbeat = Message::Heartbeat(HeartbeatMessage{time:Local::now()});
-                let event = Event::default().event(heartbeat.to_string()).json_data(heartbeat).expect("fail to transfer event to json");
-                if tx_msg.send(Ok(event)).is_err() {
+                if tx_msg.send(heartbeat).is_err() {
                     break;
                 }
             }
+            // 客户端断开后及时结束，不必等到下一次推送失败
+            _ = tx_msg.closed() => break,
+
+        }
+    }
+}
+
+/// 单聊消息推送给接收者后记录送达回执
+fn ack_delivery(app_state: &AppState, uid: i32, message: &ChatMessage) {
+    if let Err(err) = message::deliver(uid, message, app_state) {
+        let mid = message.mid;
+        warn!("fail to mark message {mid} delivered to user {uid}: {err}");
+    }
+}
+
+/// 将事件转换为推送给客户端的消息，已补发过的消息不再重复推送
+fn forward(
+    tx_msg: &UnboundedSender<Message>,
+    event: &BroadcastEvent,
+    replayed_mid: Option<i64>,
+) -> Result<(), ()> {
+    let message = match event {
+        BroadcastEvent::Chat { message, .. } => {
+            if replayed_mid.is_some_and(|mid| message.mid <= mid) {
+                return Ok(());
+            }
+            Message::ChatMessage(message.clone())
+        }
+        BroadcastEvent::Edit { message, .. } => Message::Edit(message.clone()),
+        BroadcastEvent::Recall {
+            mid,
+            target,
+            recalled_by,
+            ..
+        } => Message::Recall(RecallMessage {
+            mid: *mid,
+            target: *target,
+            recalled_by: *recalled_by,
+        }),
+        BroadcastEvent::Thread {
+            root,
+            target,
+            summary,
+            ..
+        } => Message::Thread(ThreadMessage {
+            root: *root,
+            target: *target,
+            thread: *summary,
+        }),
+        BroadcastEvent::Pin {
+            mid,
+            target,
+            pinned,
+            operator,
+            ..
+        } => Message::Pin(PinMessage {
+            mid: *mid,
+            target: *target,
+            pinned: *pinned,
+            operator: *operator,
+        }),
+        BroadcastEvent::Presence { uid, presence, .. } => Message::Presence(PresenceMessage {
+            uid: *uid,
+            presence: *presence,
+        }),
+        BroadcastEvent::Typing {
+            target,
+            uid,
+            expires_in,
+            ..
+        } => Message::Typing(TypingMessage {
+            target: *target,
+            uid: *uid,
+            expires_in: *expires_in,
+        }),
+        BroadcastEvent::Seen { gid, seen, .. } => Message::Seen(SeenMessage {
+            target: MessageTarget::Group(MessageTargetGroup { gid: *gid }),
+            seen: seen.clone(),
+        }),
+        BroadcastEvent::Receipt {
+            uid,
+            mids,
+            status,
+            at,
+            ..
+        } => Message::Receipt(ReceiptMessage {
+            uid: *uid,
+            mids: mids.clone(),
+            status: *status,
+            at: *at,
+        }),
+        BroadcastEvent::Ttl {
+            target,
+            ttl,
+            operator,
+            ..
+        } => Message::Ttl(TtlMessage {
+            target: *target,
+            ttl: *ttl,
+            operator: *operator,
+        }),
+        BroadcastEvent::Reaction {
+            mid,
+            target,
+            uid,
+            emoji,
+            added,
+            count,
+            ..
+        } => Message::Reaction(ReactionMessage {
+            mid: *mid,
+            target: *target,
+            uid: *uid,
+            emoji: emoji.clone(),
+            added: *added,
+            count: *count,
+        }),
+    };
+    tx_msg.send(message).map_err(|_| ())
+}
 
+/// 补发after之后的消息，返回最后补发的消息id，连接已断开时返回None
+fn replay(
+    tx_msg: &UnboundedSender<Message>,
+    app_state: &AppState,
+    current_uid: i32,
+    mut after: i64,
+) -> Option<i64> {
+    loop {
+        let msgs =
+            match message::get_user_msg_after(app_state, current_uid, Some(after), REPLAY_BATCH) {
+                Ok(msgs) => msgs,
+                Err(err) => {
+                    warn!("fail to replay messages of user {current_uid} after {after}: {err}");
+                    return Some(after);
+                }
+            };
+        let count = msgs.len();
+        for msg in msgs {
+            after = msg.mid;
+            ack_delivery(app_state, current_uid, &msg);
+            if tx_msg.send(Message::ChatMessage(msg)).is_err() {
+                return None;
+            }
+        }
+        if count < REPLAY_BATCH {
+            return Some(after);
         }
     }
 }
@@ -112,6 +453,32 @@ async fn event_loop(
 pub enum Message {
     ChatMessage(ChatMessage),
     Heartbeat(HeartbeatMessage),
+    CommandResult(CommandResult),
+    Recall(RecallMessage),
+    Edit(ChatMessage),
+    Reaction(ReactionMessage),
+    Thread(ThreadMessage),
+    Pin(PinMessage),
+    Ttl(TtlMessage),
+    Receipt(ReceiptMessage),
+    Seen(SeenMessage),
+    Typing(TypingMessage),
+    Presence(PresenceMessage),
+    Resync(ResyncMessage),
+}
+
+impl Message {
+    fn into_event(self) -> Event {
+        let event = match &self {
+            // 以消息id作为sse的id，断线重连时浏览器会通过Last-Event-ID带回
+            Message::ChatMessage(message) => Event::default().id(message.mid.to_string()),
+            _ => Event::default(),
+        };
+        event
+            .event(self.to_string())
+            .json_data(self)
+            .expect("fail to transfer event to json")
+    }
 }
 
 // 也可以使用strum库来实现
@@ -123,6 +490,18 @@ impl Display for Message {
             match self {
                 Message::ChatMessage(_) => "Chat",
                 Message::Heartbeat(_) => "Heartbeat",
+                Message::CommandResult(_) => "CommandResult",
+                Message::Recall(_) => "Recall",
+                Message::Edit(_) => "Edit",
+                Message::Reaction(_) => "Reaction",
+                Message::Thread(_) => "Thread",
+                Message::Pin(_) => "Pin",
+                Message::Ttl(_) => "Ttl",
+                Message::Receipt(_) => "Receipt",
+                Message::Seen(_) => "Seen",
+                Message::Typing(_) => "Typing",
+                Message::Presence(_) => "Presence",
+                Message::Resync(_) => "Resync",
             }
         )
     }
@@ -134,6 +513,148 @@ pub struct HeartbeatMessage {
     time: DateTime<Local>,
 }
 
+/// 连接消费过慢丢弃了无法补发的事件，客户端收到后应通过增量同步重新拉取会话状态
+#[derive(Debug, Clone, Serialize)]
+pub struct ResyncMessage {
+    #[serde(with = "datetime_format")]
+    time: DateTime<Local>,
+}
+
+/// 消息撤回通知，客户端收到后从界面上移除该消息
+#[derive(Debug, Clone, Serialize)]
+pub struct RecallMessage {
+    mid: i64,
+    /// 消息所在的会话
+    target: MessageTarget,
+    /// 撤回人id
+    recalled_by: i32,
+}
+
+/// 表情回应变化通知
+#[derive(Debug, Clone, Serialize)]
+pub struct ReactionMessage {
+    mid: i64,
+    /// 消息所在的会话
+    target: MessageTarget,
+    /// 回应人id
+    uid: i32,
+    emoji: String,
+    /// true为添加回应，false为取消回应
+    added: bool,
+    /// 变化后该表情的回应人数
+    count: usize,
+}
+
+/// 话题统计变化通知，未订阅话题的成员据此更新根消息上的回复数
+#[derive(Debug, Clone, Serialize)]
+pub struct ThreadMessage {
+    /// 话题的根消息id
+    root: i64,
+    /// 话题所在的会话
+    target: MessageTarget,
+    thread: ThreadSummary,
+}
+
+/// 置顶变化通知
+#[derive(Debug, Clone, Serialize)]
+pub struct PinMessage {
+    mid: i64,
+    /// 消息所在的会话
+    target: MessageTarget,
+    /// true为置顶，false为取消置顶
+    pinned: bool,
+    /// 操作人id
+    operator: i32,
+}
+
+/// 会话的消息保留时长变化通知
+#[derive(Debug, Clone, Serialize)]
+pub struct TtlMessage {
+    /// 变化的会话
+    target: MessageTarget,
+    /// 消息保留时长（秒），为空表示消息不过期
+    ttl: Option<u64>,
+    /// 操作人id
+    operator: i32,
+}
+
+/// 单聊消息的回执通知，推送给消息的发送者
+#[derive(Debug, Clone, Serialize)]
+pub struct ReceiptMessage {
+    /// 接收者id
+    uid: i32,
+    mids: Vec<i64>,
+    status: ReceiptStatus,
+    #[serde(with = "datetime_format")]
+    at: DateTime<Local>,
+}
+
+/// 群消息已读人数变化通知，推送给消息的发送者
+#[derive(Debug, Clone, Serialize)]
+pub struct SeenMessage {
+    /// 消息所在的群
+    target: MessageTarget,
+    seen: Vec<SeenCount>,
+}
+
+/// 正在输入通知，expires_in秒内未再收到时视为停止输入
+#[derive(Debug, Clone, Serialize)]
+pub struct TypingMessage {
+    /// 输入所在的会话
+    target: MessageTarget,
+    /// 正在输入的用户id
+    uid: i32,
+    expires_in: u64,
+}
+
+/// 好友在线状态变化通知
+#[derive(Debug, Clone, Serialize)]
+pub struct PresenceMessage {
+    /// 好友id
+    uid: i32,
+    #[serde(flatten)]
+    presence: PresenceVo,
+}
+
+/// websocket指令的执行结果
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandResult {
+    /// 对应指令的seq
+    seq: Option<u64>,
+    /// 发送成功后的消息id
+    mid: Option<i64>,
+    /// 失败原因
+    err: Option<String>,
+}
+
+impl CommandResult {
+    fn new(seq: u64, result: Result<i64, ServerError>) -> Self {
+        match result {
+            Ok(mid) => CommandResult {
+                seq: Some(seq),
+                mid: Some(mid),
+                err: None,
+            },
+            Err(err) => {
+                warn!("fail to handle websocket command {seq}: {err}");
+                CommandResult {
+                    seq: Some(seq),
+                    mid: None,
+                    err: Some(err.to_string()),
+                }
+            }
+        }
+    }
+
+    fn invalid(err: String) -> Self {
+        CommandResult {
+            seq: None,
+            mid: None,
+            err: Some(format!("invalid command: {err}")),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum BroadcastEvent {
     /// Chat message
@@ -141,4 +662,111 @@ pub enum BroadcastEvent {
         targets: BTreeSet<i32>,
         message: ChatMessage,
     },
+    /// Message edited
+    Edit {
+        targets: BTreeSet<i32>,
+        message: ChatMessage,
+    },
+    /// Message recalled
+    Recall {
+        targets: BTreeSet<i32>,
+        mid: i64,
+        target: MessageTarget,
+        recalled_by: i32,
+    },
+    /// Thread replied, the reply itself is sent to subscribers as a chat message
+    Thread {
+        targets: BTreeSet<i32>,
+        root: i64,
+        target: MessageTarget,
+        summary: ThreadSummary,
+    },
+    /// Message pinned or unpinned
+    Pin {
+        targets: BTreeSet<i32>,
+        mid: i64,
+        target: MessageTarget,
+        pinned: bool,
+        operator: i32,
+    },
+    /// DM messages delivered to or read by the recipient
+    Receipt {
+        targets: BTreeSet<i32>,
+        uid: i32,
+        mids: Vec<i64>,
+        status: ReceiptStatus,
+        at: DateTime<Local>,
+    },
+    /// User went online, idle or offline
+    Presence {
+        targets: BTreeSet<i32>,
+        uid: i32,
+        presence: PresenceVo,
+    },
+    /// User is typing, not persisted
+    Typing {
+        targets: BTreeSet<i32>,
+        target: MessageTarget,
+        uid: i32,
+        expires_in: u64,
+    },
+    /// Seen-by counts of group messages changed
+    Seen {
+        targets: BTreeSet<i32>,
+        gid: i32,
+        seen: Vec<SeenCount>,
+    },
+    /// Message ttl of a conversation changed
+    Ttl {
+        targets: BTreeSet<i32>,
+        target: MessageTarget,
+        ttl: Option<u64>,
+        operator: i32,
+    },
+    /// Reaction added or removed
+    Reaction {
+        targets: BTreeSet<i32>,
+        mid: i64,
+        target: MessageTarget,
+        uid: i32,
+        emoji: String,
+        added: bool,
+        count: usize,
+    },
+}
+
+impl BroadcastEvent {
+    /// 事件需要推送的用户
+    pub fn targets(&self) -> &BTreeSet<i32> {
+        match self {
+            BroadcastEvent::Chat { targets, .. } => targets,
+            BroadcastEvent::Edit { targets, .. } => targets,
+            BroadcastEvent::Recall { targets, .. } => targets,
+            BroadcastEvent::Reaction { targets, .. } => targets,
+            BroadcastEvent::Thread { targets, .. } => targets,
+            BroadcastEvent::Pin { targets, .. } => targets,
+            BroadcastEvent::Ttl { targets, .. } => targets,
+            BroadcastEvent::Receipt { targets, .. } => targets,
+            BroadcastEvent::Seen { targets, .. } => targets,
+            BroadcastEvent::Typing { targets, .. } => targets,
+            BroadcastEvent::Presence { targets, .. } => targets,
+        }
+    }
+
+    /// 聊天消息的id，lag时用于从消息库补发
+    pub fn mid(&self) -> Option<i64> {
+        match self {
+            BroadcastEvent::Chat { message, .. } => Some(message.mid),
+            BroadcastEvent::Edit { .. }
+            | BroadcastEvent::Recall { .. }
+            | BroadcastEvent::Reaction { .. }
+            | BroadcastEvent::Thread { .. }
+            | BroadcastEvent::Pin { .. }
+            | BroadcastEvent::Ttl { .. }
+            | BroadcastEvent::Receipt { .. }
+            | BroadcastEvent::Seen { .. }
+            | BroadcastEvent::Typing { .. }
+            | BroadcastEvent::Presence { .. } => None,
+        }
+    }
 }
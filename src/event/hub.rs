@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::warn;
+
+use crate::event::BroadcastEvent;
+
+/// 单个连接缓存的事件数量，超过后连接进入lag状态
+const CONNECTION_BUFFER: usize = 128;
+
+/// 按用户路由事件的连接中心，一个用户可同时存在多个连接（多端登陆）
+#[derive(Default)]
+pub struct Hub {
+    next_id: AtomicU64,
+    connections: RwLock<HashMap<i32, HashMap<u64, Sink>>>,
+}
+
+struct Sink {
+    sender: mpsc::Sender<Arc<BroadcastEvent>>,
+    lag: Arc<Mutex<Lag>>,
+}
+
+/// 连接消费过慢时，hub不再向其投递事件，直到连接消费完积压的事件并补发丢弃的消息
+#[derive(Default)]
+struct Lag {
+    lagged: bool,
+    /// lag期间丢弃的聊天消息中最小的消息id
+    dropped_from: Option<i64>,
+    /// lag期间是否丢弃了聊天消息以外的事件，这些事件无法补发，客户端需要重新同步
+    dropped_others: bool,
+}
+
+/// 连接从lag中恢复时需要补偿的内容
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Recovery {
+    /// lag期间丢弃的第一条聊天消息id，从消息库补发
+    pub dropped_from: Option<i64>,
+    /// 是否需要通知客户端重新同步
+    pub resync: bool,
+}
+
+impl Hub {
+    pub fn register(self: &Arc<Self>, uid: i32) -> Connection {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::channel(CONNECTION_BUFFER);
+        let lag = Arc::new(Mutex::new(Lag::default()));
+        self.connections
+            .write()
+            .unwrap()
+            .entry(uid)
+            .or_default()
+            .insert(
+                id,
+                Sink {
+                    sender,
+                    lag: lag.clone(),
+                },
+            );
+        Connection {
+            id,
+            uid,
+            receiver,
+            lag,
+            hub: self.clone(),
+        }
+    }
+
+    fn unregister(&self, uid: i32, id: u64) {
+        let mut connections = self.connections.write().unwrap();
+        if let Some(sinks) = connections.get_mut(&uid) {
+            sinks.remove(&id);
+            if sinks.is_empty() {
+                connections.remove(&uid);
+            }
+        }
+    }
+
+    /// 将事件投递给事件的目标用户的所有连接
+    pub fn send(&self, event: BroadcastEvent) {
+        let event = Arc::new(event);
+        let connections = self.connections.read().unwrap();
+        for uid in event.targets() {
+            let Some(sinks) = connections.get(uid) else {
+                continue;
+            };
+            for (id, sink) in sinks {
+                let mut lag = sink.lag.lock().unwrap();
+                if !lag.lagged {
+                    match sink.sender.try_send(event.clone()) {
+                        Ok(_) => continue,
+                        Err(TrySendError::Full(_)) => {
+                            warn!("connection {id} of user {uid} lagged");
+                            lag.lagged = true;
+                        }
+                        // 连接已关闭，等待Connection drop时注销
+                        Err(TrySendError::Closed(_)) => continue,
+                    }
+                }
+                match event.mid() {
+                    Some(mid) => {
+                        lag.dropped_from = Some(lag.dropped_from.map_or(mid, |from| from.min(mid)))
+                    }
+                    None => lag.dropped_others = true,
+                }
+            }
+        }
+    }
+
+    /// 在线用户数
+    pub fn online_users(&self) -> usize {
+        self.connections.read().unwrap().len()
+    }
+
+    /// 在线连接数
+    pub fn online_connections(&self) -> usize {
+        self.connections
+            .read()
+            .unwrap()
+            .values()
+            .map(|sinks| sinks.len())
+            .sum()
+    }
+
+    /// 用户的在线连接数
+    pub fn connections_of(&self, uid: i32) -> usize {
+        self.connections
+            .read()
+            .unwrap()
+            .get(&uid)
+            .map_or(0, |sinks| sinks.len())
+    }
+}
+
+/// 用户的一个连接，drop时自动从hub注销
+pub struct Connection {
+    id: u64,
+    uid: i32,
+    receiver: mpsc::Receiver<Arc<BroadcastEvent>>,
+    lag: Arc<Mutex<Lag>>,
+    hub: Arc<Hub>,
+}
+
+impl Connection {
+    pub async fn recv(&mut self) -> Option<Arc<BroadcastEvent>> {
+        self.receiver.recv().await
+    }
+
+    pub fn try_recv(&mut self) -> Option<Arc<BroadcastEvent>> {
+        self.receiver.try_recv().ok()
+    }
+
+    pub fn is_lagged(&self) -> bool {
+        self.lag.lock().unwrap().lagged
+    }
+
+    /// 积压的事件消费完后调用，恢复投递，返回lag期间丢弃的事件需要的补偿
+    pub fn recover(&self) -> Recovery {
+        let mut lag = self.lag.lock().unwrap();
+        lag.lagged = false;
+        Recovery {
+            dropped_from: lag.dropped_from.take(),
+            resync: std::mem::take(&mut lag.dropped_others),
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.hub.unregister(self.uid, self.id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+    use std::sync::Arc;
+
+    use chrono::Local;
+
+    use crate::event::hub::Hub;
+    use crate::event::BroadcastEvent;
+    use crate::message::{
+        ChatMessage, ChatMessagePayload, MessageContent, MessageDetail, MessageNormal,
+        MessageTarget, MessageTargetUser,
+    };
+
+    fn chat(mid: i64, from_uid: i32, to_uid: i32) -> BroadcastEvent {
+        BroadcastEvent::Chat {
+            targets: BTreeSet::from([from_uid, to_uid]),
+            message: ChatMessage::new(
+                mid,
+                ChatMessagePayload {
+                    from_uid,
+                    created_at: Local::now(),
+                    target: MessageTarget::User(MessageTargetUser { uid: to_uid }),
+                    detail: MessageDetail::Normal(MessageNormal {
+                        content: MessageContent {
+                            content: "hello".to_string(),
+                            body: None,
+                            mentions: None,
+                        },
+                    }),
+                    edited_at: None,
+                    thread: None,
+                    expire_at: None,
+                },
+            ),
+        }
+    }
+
+    #[tokio::test]
+    async fn route_to_targets_only() {
+        let hub = Arc::new(Hub::default());
+        let mut phone = hub.register(1);
+        let mut desktop = hub.register(1);
+        let mut other = hub.register(3);
+        assert_eq!(hub.online_users(), 2);
+        assert_eq!(hub.connections_of(1), 2);
+
+        hub.send(chat(1, 2, 1));
+        assert_eq!(phone.recv().await.unwrap().mid(), Some(1));
+        assert_eq!(desktop.recv().await.unwrap().mid(), Some(1));
+        assert!(other.try_recv().is_none());
+
+        drop(desktop);
+        assert_eq!(hub.connections_of(1), 1);
+        assert_eq!(hub.online_connections(), 2);
+    }
+
+    #[tokio::test]
+    async fn lagged_connection_keeps_registered() {
+        let hub = Arc::new(Hub::default());
+        let mut connection = hub.register(1);
+        for mid in 1..=200 {
+            hub.send(chat(mid, 2, 1));
+        }
+        assert!(connection.is_lagged());
+        assert_eq!(hub.connections_of(1), 1);
+        while connection.try_recv().is_some() {}
+        let recovery = connection.recover();
+        assert_eq!(recovery.dropped_from, Some(129));
+        assert!(!recovery.resync);
+        assert!(!connection.is_lagged());
+
+        for mid in 201..=400 {
+            hub.send(chat(mid, 2, 1));
+        }
+        hub.send(BroadcastEvent::Typing {
+            targets: BTreeSet::from([1]),
+            target: MessageTarget::User(MessageTargetUser { uid: 1 }),
+            uid: 2,
+            expires_in: 5,
+        });
+        while connection.try_recv().is_some() {}
+        assert!(connection.recover().resync);
+    }
+}
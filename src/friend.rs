@@ -5,6 +5,7 @@ use crate::auth::Token;
 use crate::datetime::datetime_format;
 use crate::err::{ErrPrint, ServerError};
 use crate::friend::dgraph::{FriendVo, Location, Point};
+use crate::presence::{self, PresenceVo};
 use crate::{datetime, middleware, user, Api, Res};
 use axum::extract::{Path, State};
 use axum::routing::{get, patch, post};
@@ -193,25 +194,49 @@ async fn review(
 struct Friend {
     id: i32,
     name: String,
+    /// 好友的在线状态
+    #[serde(flatten)]
+    presence: PresenceVo,
 }
 
 /// 好友列表
-async fn list(token: Token) -> Res<Json<Vec<Friend>>> {
-    match dgraph::get_friends(token.dgraph_uid.as_str()).await? {
-        None => Ok(Json(vec![])),
+async fn list(State(app_state): State<AppState>, token: Token) -> Res<Json<Vec<Friend>>> {
+    let friends = match dgraph::get_friends(token.dgraph_uid.as_str()).await? {
+        None => return Ok(Json(vec![])),
         Some(res) => match res.friend {
-            None => Ok(Json(vec![])),
-            Some(friends) => Ok(Json(
-                friends
-                    .iter()
-                    .map(|friend| Friend {
-                        id: friend.user_id,
-                        name: friend.name.clone(),
-                    })
-                    .collect(),
-            )),
+            None => return Ok(Json(vec![])),
+            Some(friends) => friends,
         },
-    }
+    };
+    let id_2_presence = user::get_by_ids(friends.iter().map(|x| x.user_id).collect(), &app_state)
+        .await?
+        .iter()
+        .map(|user| (user.id, presence::get_presence(user, &app_state)))
+        .collect::<HashMap<i32, PresenceVo>>();
+    Ok(Json(
+        friends
+            .iter()
+            .map(|friend| Friend {
+                id: friend.user_id,
+                name: friend.name.clone(),
+                presence: id_2_presence
+                    .get(&friend.user_id)
+                    .copied()
+                    .unwrap_or_default(),
+            })
+            .collect(),
+    ))
+}
+
+/// 查询好友的用户id
+pub(crate) async fn get_friend_ids(dgraph_uid: &str) -> Result<Vec<i32>, ServerError> {
+    Ok(dgraph::get_friends(dgraph_uid)
+        .await?
+        .and_then(|res| res.friend)
+        .unwrap_or_default()
+        .iter()
+        .map(|friend| friend.user_id)
+        .collect())
 }
 
 pub(crate) struct FriendRegister {
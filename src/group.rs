@@ -1,7 +1,7 @@
 use std::collections::{HashMap, HashSet};
 
 use crate::datetime::datetime_format;
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::routing::{delete, get, patch, post, put};
 use axum::{Json, Router};
 use chrono::{DateTime, Local};
@@ -17,25 +17,29 @@ use validator::Validate;
 use entity::group::Model;
 use entity::prelude::{Group, UserGroupRel};
 use entity::{group, user_group_rel};
+use msg::Conversation;
 
 use crate::app_state::AppState;
 use crate::auth::Token;
 use crate::err::{ErrPrint, ServerError};
 use crate::message::{
-    HistoryMsgGroup, HistoryMsgReq, HistoryReq, MessageTarget, MessageTargetGroup, SendMsgReq,
+    ChatMessage, ChatMessagePayload, ContentBody, HistoryMsgGroup, HistoryMsgReq, HistoryPage,
+    HistoryQuery, MessageDetail, MessageErr, MessageTarget, MessageTargetGroup, PinnedMsg, Quote,
+    ReactionSummary, SendMsgReq, ThreadSummary, Ttl,
 };
 use crate::read_index::UpdateReadIndex;
 use crate::user::UserErr;
 use crate::validate::ValidatedJson;
-use crate::{message, middleware, read_index, user, Api, Res};
+use crate::{datetime, message, middleware, read_index, typing, user, Api, Res};
 
 #[derive(OpenApi)]
 #[openapi(
     paths(
-        all, create, add
+        all, create, add, history
     ),
     components(
-        schemas(GroupRes, CreateReq)
+        schemas(GroupRes, CreateReq, GroupErr, GroupHistoryMsg, message::GroupHistoryPage,
+            message::ThreadSummary)
     ),
     tags(
         (name = "group", description = "Group API")
@@ -52,6 +56,9 @@ impl Api for GroupApi {
             .route("/:gid/send", put(send))
             .route("/:gid/admin/:uid", patch(admin))
             .route("/:gid/forbid/:uid", put(forbid).delete(un_forbid))
+            .route("/:gid/ttl", put(set_ttl))
+            .route("/:gid/typing", post(typing))
+            .route("/:gid/history", delete(clear_history))
             .route_layer(axum::middleware::from_fn_with_state(
                 app_state.clone(),
                 middleware::check_user_status,
@@ -59,7 +66,10 @@ impl Api for GroupApi {
             .route("/:gid", get(detail))
             .route("/", get(mine))
             .route("/all", get(all))
+            .route("/mentions", get(mentions))
             .route("/:gid/history", get(history))
+            .route("/:gid/pins", get(pins))
+            .route("/:gid/ttl", get(ttl))
             .route_layer(axum::middleware::from_fn_with_state(
                 app_state.clone(),
                 middleware::check_login,
@@ -87,6 +97,8 @@ pub enum GroupErr {
     YouAreNotAdmin,
     #[error("您已被禁言，无权发言")]
     YouAreForbid,
+    #[error("只有群管理员可以@所有人")]
+    MentionAllNotAllowed,
 }
 
 impl ErrPrint for GroupErr {}
@@ -368,6 +380,49 @@ async fn detail(
     }
 }
 
+/// 判断用户是否在群内
+pub(crate) async fn in_group(gid: i32, uid: i32, app_state: &AppState) -> Result<bool, DbErr> {
+    Ok(check_group_status(gid, uid, app_state).await?.in_group)
+}
+
+/// 用户加入的群
+#[derive(Serialize, Debug)]
+pub struct Membership {
+    pub gid: i32,
+    /// 入群时间
+    #[serde(with = "datetime_format")]
+    pub joined_at: DateTime<Local>,
+}
+
+/// 查询用户当前加入的所有群
+pub(crate) async fn get_memberships(
+    uid: i32,
+    app_state: &AppState,
+) -> Result<Vec<Membership>, DbErr> {
+    Ok(UserGroupRel::find()
+        .filter(user_group_rel::Column::UserId.eq(uid))
+        .all(&app_state.db)
+        .await?
+        .into_iter()
+        .map(|ugr| Membership {
+            gid: ugr.group_id,
+            joined_at: datetime::native_datetime_2_datetime(ugr.c_time),
+        })
+        .collect())
+}
+
+/// 判断用户是否是群管理员
+pub(crate) async fn is_admin(
+    gid: i32,
+    uid: i32,
+    app_state: &AppState,
+) -> Result<bool, ServerError> {
+    match Group::find_by_id(gid).one(&app_state.db).await? {
+        None => Err(GroupErr::GroupNotExist(gid).into()),
+        Some(group) => Ok(group.admin == uid),
+    }
+}
+
 pub(crate) async fn get_uids(app_state: &AppState, gid: i32) -> Result<Vec<i32>, DbErr> {
     Ok(get_rels(&app_state, gid)
         .await?
@@ -485,18 +540,28 @@ async fn send(
     token: Token,
     ValidatedJson(msg): ValidatedJson<SendMsgReq>,
 ) -> Res<String> {
-    let s = check_group_status(gid, token.id, &app_state).await?;
-    if !s.in_group {
-        return Err(GroupErr::UserNotInGroup { uid: token.id, gid }.into());
-    };
-    if s.forbid {
-        return Err(GroupErr::YouAreForbid.into());
-    }
-    let payload = msg.build_payload(token.id, MessageTarget::Group(MessageTargetGroup { gid }));
-    let mid = message::send_msg(payload, &app_state).await?;
+    let target = MessageTarget::Group(MessageTargetGroup { gid });
+    let mid = message::send_once(target, msg, &app_state, &token).await?;
+    Ok(mid.to_string())
+}
+
+/// 向群发送消息，供http接口与websocket共用
+pub(crate) async fn send_to_group(
+    app_state: &AppState,
+    token: &Token,
+    gid: i32,
+    detail: MessageDetail,
+) -> Result<i64, ServerError> {
+    check_can_send(gid, token.id, app_state).await?;
+    let payload = ChatMessagePayload::new(
+        token.id,
+        MessageTarget::Group(MessageTargetGroup { gid }),
+        detail,
+    );
+    let mid = message::send_msg(payload, app_state).await?;
     // 设置当前用户的read_index
     read_index::set_read_index(
-        &app_state,
+        app_state,
         token.id,
         UpdateReadIndex::Group {
             target_gid: gid,
@@ -504,7 +569,169 @@ async fn send(
         },
     )
     .await?;
-    Ok(mid.to_string())
+    Ok(mid)
+}
+
+/// 查询群中置顶的消息
+async fn pins(
+    State(app_state): State<AppState>,
+    token: Token,
+    Path(gid): Path<i32>,
+) -> Res<Json<Vec<PinnedMsg>>> {
+    if !in_group(gid, token.id, &app_state).await? {
+        return Err(GroupErr::UserNotInGroup { uid: token.id, gid }.into());
+    }
+    Ok(Json(message::get_pins(
+        Conversation::Group(gid as i64),
+        &app_state,
+    )?))
+}
+
+/// 清空群聊，只对自己生效
+async fn clear_history(
+    State(app_state): State<AppState>,
+    token: Token,
+    Path(gid): Path<i32>,
+) -> Res<()> {
+    if !in_group(gid, token.id, &app_state).await? {
+        return Err(GroupErr::UserNotInGroup { uid: token.id, gid }.into());
+    }
+    message::clear_conversation(token.id, Conversation::Group(gid as i64), &app_state)?;
+    Ok(())
+}
+
+/// 查询群的消息保留时长
+async fn ttl(
+    State(app_state): State<AppState>,
+    token: Token,
+    Path(gid): Path<i32>,
+) -> Res<Json<Ttl>> {
+    if !in_group(gid, token.id, &app_state).await? {
+        return Err(GroupErr::UserNotInGroup { uid: token.id, gid }.into());
+    }
+    let ttl = message::get_ttl(Conversation::Group(gid as i64), &app_state)?;
+    Ok(Json(ttl))
+}
+
+/// 设置群的消息保留时长，仅群管理员可以设置
+async fn set_ttl(
+    State(app_state): State<AppState>,
+    token: Token,
+    Path(gid): Path<i32>,
+    ValidatedJson(ttl): ValidatedJson<Ttl>,
+) -> Res<()> {
+    if !is_admin(gid, token.id, &app_state).await? {
+        return Err(MessageErr::NoPermission.into());
+    }
+    let target = MessageTarget::Group(MessageTargetGroup { gid });
+    message::set_ttl(target, ttl, &app_state, &token).await?;
+    Ok(())
+}
+
+/// 通知群内其他成员当前用户正在输入
+async fn typing(State(app_state): State<AppState>, token: Token, Path(gid): Path<i32>) -> Res<()> {
+    let target = MessageTarget::Group(MessageTargetGroup { gid });
+    typing::send(target, &app_state, &token).await?;
+    Ok(())
+}
+
+/// 每个群最多返回的未读@消息数量
+const MAX_UNREAD_MENTIONS: usize = 100;
+
+/// 未读的@我的消息
+#[derive(Serialize)]
+struct MentionVo {
+    gid: i32,
+    mid: i64,
+    from_uid: i32,
+    msg: String,
+    #[serde(with = "datetime_format")]
+    time: DateTime<Local>,
+}
+
+/// 查询所在的群中上次已读之后@我的消息，按消息id升序返回
+async fn mentions(State(app_state): State<AppState>, token: Token) -> Res<Json<Vec<MentionVo>>> {
+    let gids = get_memberships(token.id, &app_state)
+        .await?
+        .into_iter()
+        .map(|membership| membership.gid)
+        .collect::<Vec<i32>>();
+    let gid_2_read_mid = entity::read_index::Entity::find()
+        .filter(entity::read_index::Column::Uid.eq(token.id))
+        .filter(entity::read_index::Column::TargetGid.is_in(gids.iter().copied()))
+        .all(&app_state.db)
+        .await?
+        .into_iter()
+        .filter_map(|ri| ri.target_gid.map(|gid| (gid, ri.mid)))
+        .collect::<HashMap<i32, Option<i64>>>();
+    let mut mids = vec![];
+    for gid in gids {
+        let read_mid = gid_2_read_mid.get(&gid).copied().flatten();
+        mids.extend(message::get_unread_mentions(
+            token.id,
+            gid,
+            read_mid,
+            MAX_UNREAD_MENTIONS,
+            &app_state,
+        )?);
+    }
+    mids.sort_unstable();
+    let mentions = message::get_by_mids(mids, &app_state)
+        .into_iter()
+        .filter(|x| !matches!(x.payload.detail, MessageDetail::Recall(_)))
+        .filter_map(|x| match x.payload.target {
+            MessageTarget::Group(MessageTargetGroup { gid }) => Some(MentionVo {
+                gid,
+                mid: x.mid,
+                from_uid: x.payload.from_uid,
+                msg: x.payload.detail.get_content(),
+                time: x.payload.created_at,
+            }),
+            MessageTarget::User(_) => None,
+        })
+        .collect();
+    Ok(Json(mentions))
+}
+
+/// 在话题中回复，回复者自动订阅话题，根消息的发送者在话题创建时自动订阅
+pub(crate) async fn send_to_thread(
+    app_state: &AppState,
+    token: &Token,
+    gid: i32,
+    root: &ChatMessage,
+    detail: MessageDetail,
+) -> Result<i64, ServerError> {
+    check_can_send(gid, token.id, app_state).await?;
+    let mut subscribers = vec![token.id];
+    if message::get_threads([root.mid], app_state)?.is_empty() {
+        subscribers.push(root.payload.from_uid);
+    }
+    message::set_thread_subscriptions(root.mid, subscribers, app_state)?;
+    let payload = ChatMessagePayload {
+        thread: Some(root.mid),
+        ..ChatMessagePayload::new(
+            token.id,
+            MessageTarget::Group(MessageTargetGroup { gid }),
+            detail,
+        )
+    };
+    message::send_msg(payload, app_state).await
+}
+
+/// 校验用户在群内且未被禁言
+pub(crate) async fn check_can_send(
+    gid: i32,
+    uid: i32,
+    app_state: &AppState,
+) -> Result<(), ServerError> {
+    let s = check_group_status(gid, uid, app_state).await?;
+    if !s.in_group {
+        return Err(GroupErr::UserNotInGroup { uid, gid }.into());
+    };
+    if s.forbid {
+        return Err(GroupErr::YouAreForbid.into());
+    }
+    Ok(())
 }
 
 pub(crate) async fn get_by_gids(gids: Vec<i32>, app_state: &AppState) -> Result<Vec<Model>, DbErr> {
@@ -514,39 +741,71 @@ pub(crate) async fn get_by_gids(gids: Vec<i32>, app_state: &AppState) -> Result<
         .await
 }
 
-#[derive(Serialize)]
-struct GroupHistoryMsg {
+/// 群聊历史消息
+#[derive(Serialize, ToSchema)]
+pub struct GroupHistoryMsg {
+    /// 消息id
     mid: i64,
+    /// 消息内容
     msg: String,
+    /// 消息发送时间
     #[serde(with = "datetime_format")]
     time: DateTime<Local>,
+    /// 消息发送者id
     from_uid: i32,
+    /// 消息发送者的名字
     name_of_from_uid: String,
+    /// 是否编辑过
+    edited: bool,
+    /// 非文本消息的内容
+    body: Option<ContentBody>,
+    /// 引用的消息
+    quote: Option<Quote>,
+    /// 表情回应
+    reactions: Vec<ReactionSummary>,
+    /// 以该消息为根的话题
+    thread: Option<ThreadSummary>,
 }
 
+#[utoipa::path(
+    get,
+    path = "/{gid}/history",
+    params(
+        ("gid" = i32, Path, description = "id of group"),
+        ("before" = Option<i64>, Query, description = "messages before this mid"),
+        ("after" = Option<i64>, Query, description = "messages after this mid"),
+        ("around" = Option<i64>, Query, description = "this mid and messages around it"),
+        ("limit" = Option<usize>, Query, description = "page size, 50 by default, 200 at most")
+    ),
+    responses(
+        (status = 200, description = "Get a page of history message successfully", body = GroupHistoryPage),
+        (status = 401, description = "User is not in the group", body = GroupErr),
+    ),
+)]
+/// 查询群聊的聊天记录
 pub(crate) async fn history(
     State(app_state): State<AppState>,
     token: Token,
     Path(gid): Path<i32>,
-) -> Res<Json<Vec<GroupHistoryMsg>>> {
+    Query(query): Query<HistoryQuery>,
+) -> Res<Json<HistoryPage<GroupHistoryMsg>>> {
+    query.validate()?;
     if !check_group_status(gid, token.id, &app_state)
         .await?
         .in_group
     {
         return Err(GroupErr::UserNotInGroup { uid: token.id, gid }.into());
     }
-    let mut history_msg = message::get_history_msg(
+    let history_msg = message::get_history_msg(
         &app_state,
+        token.id,
         HistoryMsgReq::Group(HistoryMsgGroup {
             gid,
-            history: HistoryReq {
-                before: None,
-                limit: 1000,
-            },
+            history: query.into(),
         }),
-    );
-    history_msg.sort_by(|m1, m2| m2.payload.created_at.cmp(&m1.payload.created_at));
+    )?;
     let from_uids = history_msg
+        .messages
         .iter()
         .map(|x| x.payload.from_uid)
         .collect::<Vec<i32>>();
@@ -555,9 +814,14 @@ pub(crate) async fn history(
         .iter()
         .map(|x| (x.id, x.name.clone()))
         .collect::<HashMap<i32, String>>();
-    Ok(Json(history_msg
-        .into_iter()
-        .map(|x| GroupHistoryMsg {
+    let mut reactions = message::get_reactions(
+        history_msg.messages.iter().map(|x| x.mid),
+        token.id,
+        &app_state,
+    )?;
+    let mut threads = message::get_threads(history_msg.messages.iter().map(|x| x.mid), &app_state)?;
+    Ok(Json(history_msg.map(|x| {
+        GroupHistoryMsg {
             mid: x.mid,
             msg: x.payload.detail.get_content(),
             time: x.payload.created_at,
@@ -566,6 +830,11 @@ pub(crate) async fn history(
                 .get(&x.payload.from_uid)
                 .unwrap_or(&"未知用户".to_string())
                 .to_string(),
-        })
-        .collect()))
+            edited: x.payload.edited_at.is_some(),
+            body: x.payload.detail.get_body(),
+            quote: x.quote,
+            reactions: reactions.remove(&x.mid).unwrap_or_default(),
+            thread: threads.remove(&x.mid),
+        }
+    })))
 }
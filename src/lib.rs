@@ -10,6 +10,7 @@ use serde::Serialize;
 use utoipa::ToSchema;
 
 pub mod app_state;
+pub mod attachment;
 pub mod auth;
 pub mod datetime;
 pub mod err;
@@ -20,7 +21,11 @@ pub mod log;
 pub mod message;
 pub mod middleware;
 pub mod open_api;
+pub mod presence;
 pub mod read_index;
+pub mod schedule;
+pub mod sync;
+pub mod typing;
 pub mod user;
 pub mod validate;
 pub mod admin;
@@ -10,12 +10,17 @@ use utoipa_swagger_ui::{SwaggerUi, Url};
 
 use chat_server::admin::AdminApi;
 use chat_server::app_state::AppState;
+use chat_server::attachment::AttachmentApi;
 use chat_server::auth::TokenApi;
 use chat_server::event::EventApi;
 use chat_server::friend::FriendApi;
 use chat_server::group::GroupApi;
+use chat_server::message::{self, MessageApi};
 use chat_server::open_api::swagger_ui;
+use chat_server::presence;
 use chat_server::read_index::ReadIndexApi;
+use chat_server::schedule::{self, ScheduleApi};
+use chat_server::sync::SyncApi;
 use chat_server::user::UserApi;
 use chat_server::{log, Api};
 use migration::{Migrator, MigratorTrait};
@@ -30,6 +35,9 @@ async fn main() {
     Migrator::up(&app_state.db, None)
         .await
         .expect("fail to apply migrations");
+    tokio::spawn(schedule::dispatch(app_state.clone()));
+    tokio::spawn(message::sweep_expired(app_state.clone()));
+    tokio::spawn(presence::sweep_idle(app_state.clone()));
     let app = Router::new()
         .merge(swagger_ui().await)
         .route("/", get(|| async { "Hello, World!" }))
@@ -39,6 +47,10 @@ async fn main() {
         .nest("/token", TokenApi::route(app_state.clone()))
         .nest("/event", EventApi::route(app_state.clone()))
         .nest("/friend", FriendApi::route(app_state.clone()))
+        .nest("/msg", MessageApi::route(app_state.clone()))
+        .nest("/attachment", AttachmentApi::route(app_state.clone()))
+        .nest("/schedule", ScheduleApi::route(app_state.clone()))
+        .nest("/sync", SyncApi::route(app_state.clone()))
         .nest("/ri", ReadIndexApi::route(app_state.clone()));
 
     let listener = TcpListener::bind("0.0.0.0:3000").await.unwrap();
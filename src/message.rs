@@ -1,16 +1,156 @@
 use crate::app_state::AppState;
-use crate::datetime::datetime_format;
-use crate::err::ServerError;
+use crate::attachment::{self, AttachmentErr};
+use crate::auth::Token;
+use crate::datetime::{datetime_format, opt_datetime_format};
+use crate::err::{ErrPrint, ServerError};
 use crate::event::BroadcastEvent;
-use crate::group;
+use crate::group::GroupHistoryMsg;
+use crate::user::UserHistoryMsg;
+use crate::{group, middleware, user, Api};
+use axum::routing::{get, patch, post, put};
+use axum::Router;
 use chrono::{DateTime, Local};
-use futures::{FutureExt, StreamExt};
+use msg::{Conversation, Messages};
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashMap};
 use std::fmt;
-use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
 use utoipa::ToSchema;
-use validator::Validate;
+use validator::{Validate, ValidationError, ValidationErrors};
+
+mod dedup;
+mod edit;
+mod expire;
+mod forward;
+mod hide;
+mod mention;
+mod pin;
+mod reaction;
+mod receipt;
+mod search;
+mod seen;
+mod thread;
+
+pub(crate) use dedup::send_once;
+pub(crate) use edit::get_revisions;
+pub use edit::EditMsgReq;
+use edit::{edit, recall};
+use expire::expire_msg;
+pub(crate) use expire::{get_ttl, set_ttl};
+pub use expire::{sweep_expired, Ttl};
+pub use forward::ForwardReq;
+use forward::{forward, get_merged_messages, merged};
+use hide::hide;
+pub(crate) use hide::{clear_conversation, get_cleared, get_latest_visible};
+use mention::resolve_mentions;
+pub(crate) use mention::{get_unread_mentions, has_unread_mention};
+pub(crate) use pin::get_pins;
+pub use pin::PinnedMsg;
+use pin::{pin, unpin};
+pub(crate) use reaction::get_reactions;
+pub use reaction::ReactionSummary;
+use reaction::{add_reaction, remove_reaction};
+use receipt::receipt;
+pub(crate) use receipt::{deliver, read_dm};
+pub use receipt::{ReceiptStatus, ReceiptVo};
+use search::{search, update_index};
+pub use search::{SearchHit, SearchQuery};
+pub(crate) use seen::read_group;
+use seen::seen_by;
+pub use seen::{SeenByVo, SeenCount};
+pub use thread::ThreadSummary;
+pub(crate) use thread::{get_threads, set_thread_subscriptions};
+use thread::{reply_thread, subscribe_thread, thread, unsubscribe_thread};
+
+pub struct MessageApi;
+
+impl Api for MessageApi {
+    fn route(app_state: AppState) -> Router {
+        Router::new()
+            .route("/:mid/recall", put(recall))
+            .route("/:mid", patch(edit).delete(hide))
+            .route("/forward", post(forward))
+            .route("/:mid/merged", get(merged))
+            .route("/search", get(search))
+            .route(
+                "/:mid/reactions/:emoji",
+                put(add_reaction).delete(remove_reaction),
+            )
+            .route("/:mid/thread", get(thread).post(reply_thread))
+            .route("/:mid/pin", put(pin).delete(unpin))
+            .route("/:mid/receipt", get(receipt))
+            .route("/:mid/seen", get(seen_by))
+            .route(
+                "/:mid/thread/subscription",
+                put(subscribe_thread).delete(unsubscribe_thread),
+            )
+            .route_layer(axum::middleware::from_fn_with_state(
+                app_state.clone(),
+                middleware::check_user_status,
+            ))
+            .route_layer(axum::middleware::from_fn_with_state(
+                app_state.clone(),
+                middleware::check_login,
+            ))
+            .with_state(app_state.clone())
+    }
+}
+
+/// 消息相关错误
+#[derive(Debug, Error, ToSchema)]
+pub enum MessageErr {
+    /// 消息不存在
+    #[error("消息{0}不存在")]
+    MessageNotExist(i64),
+    /// 消息已撤回
+    #[error("消息已撤回")]
+    MessageRecalled,
+    /// 超过撤回时间
+    #[error("消息发送已超过{0}秒，无法撤回")]
+    RecallTimeout(u64),
+    /// 无权操作该消息
+    #[error("您无权操作该消息")]
+    NoPermission,
+    /// 引用的消息不属于当前会话
+    #[error("引用的消息{0}不在当前会话中")]
+    QuoteNotInConversation(i64),
+    /// 合并转发的消息不属于同一会话
+    #[error("只能合并转发同一会话中的消息")]
+    ForwardAcrossConversations,
+    /// 不是合并转发的消息
+    #[error("消息{0}不是合并转发的聊天记录")]
+    NotMerged(i64),
+    /// 消息不支持编辑
+    #[error("该消息不支持编辑")]
+    NotEditable,
+    /// 表情回应不合法
+    #[error("不支持的表情回应")]
+    InvalidReaction,
+    /// 表情回应种类过多
+    #[error("单条消息最多{0}种表情回应")]
+    TooManyReactions(usize),
+    /// 单聊消息不支持@
+    #[error("只有群消息可以@成员")]
+    MentionOutsideGroup,
+    /// 消息不能作为话题
+    #[error("消息{0}不能作为话题")]
+    NotThreadable(i64),
+    /// 置顶消息过多
+    #[error("每个会话最多置顶{0}条消息")]
+    TooManyPins(usize),
+    /// 不是单聊消息
+    #[error("消息{0}不是单聊消息")]
+    NotDirectMessage(i64),
+    /// 不是群消息
+    #[error("消息{0}不是群消息")]
+    NotGroupMessage(i64),
+    /// 相同去重键的消息正在发送
+    #[error("去重键为{0}的消息正在发送，请稍后重试")]
+    SendInProgress(String),
+}
+
+impl ErrPrint for MessageErr {}
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ChatMessagePayload {
@@ -26,30 +166,107 @@ pub struct ChatMessagePayload {
 
     /// Message detail
     pub detail: MessageDetail,
+
+    /// The last edit time of the message, None if never edited.
+    #[serde(default, with = "opt_datetime_format")]
+    pub edited_at: Option<DateTime<Local>>,
+
+    /// Id of the thread root if the message is a thread reply.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thread: Option<i64>,
+
+    /// The expire time of the message, None if the conversation keeps messages forever.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "opt_datetime_format"
+    )]
+    pub expire_at: Option<DateTime<Local>>,
 }
 
 /// Send message request
-#[derive(Deserialize, Validate, Debug, ToSchema)]
+#[derive(Serialize, Deserialize, Validate, Debug, ToSchema)]
+#[validate(schema(function = "validate_send_msg_req"))]
 pub struct SendMsgReq {
-    /// Message content
-    #[validate(length(min = 1, code = "1", message = "msg is blank"))]
+    /// Message text, or the caption of non-text content
+    #[serde(default)]
     pub msg: String,
+    /// Typed body of non-text content, absent for text message
+    #[serde(default)]
+    #[validate(nested)]
+    pub body: Option<ContentBody>,
+    /// Id of the replied-to message
+    #[serde(default)]
+    pub reply_to: Option<i64>,
+    /// Members mentioned in a group message
+    #[serde(default)]
+    #[validate(nested)]
+    pub mentions: Option<Mentions>,
+    /// Client generated dedup key, a retried send with the same key returns the original mid
+    #[serde(default)]
+    #[validate(length(min = 1, max = 64))]
+    pub client_key: Option<String>,
+}
+
+fn validate_send_msg_req(req: &SendMsgReq) -> Result<(), ValidationError> {
+    if req.body.is_none() && req.msg.is_empty() {
+        return Err(ValidationError::new("1").with_message("msg is blank".into()));
+    }
+    Ok(())
 }
 
 impl SendMsgReq {
-    pub fn build_payload(self, from_uid: i32, message_target: MessageTarget) -> ChatMessagePayload {
+    pub fn into_detail(self) -> MessageDetail {
+        let content = MessageContent {
+            content: self.msg,
+            body: self.body,
+            mentions: self.mentions,
+        };
+        match self.reply_to {
+            None => MessageDetail::Normal(MessageNormal { content }),
+            Some(mid) => MessageDetail::Replay(MessageReplay { mid, content }),
+        }
+    }
+}
+
+impl ChatMessagePayload {
+    pub fn new(from_uid: i32, target: MessageTarget, detail: MessageDetail) -> Self {
         ChatMessagePayload {
             from_uid,
             created_at: Local::now(),
-            target: message_target,
-            detail: MessageDetail::Normal(MessageNormal {
-                content: MessageContent { content: self.msg },
-            }),
+            target,
+            detail,
+            edited_at: None,
+            thread: None,
+            expire_at: None,
         }
     }
-}
 
-impl ChatMessagePayload {}
+    /// 消息所属的会话
+    pub fn conversation(&self) -> Conversation {
+        match self.target {
+            MessageTarget::User(MessageTargetUser { uid }) => {
+                Conversation::dm(self.from_uid as i64, uid as i64)
+            }
+            MessageTarget::Group(MessageTargetGroup { gid }) => Conversation::Group(gid as i64),
+        }
+    }
+
+    /// 两条消息是否属于同一会话
+    fn same_conversation(&self, other: &ChatMessagePayload) -> bool {
+        match (self.target, other.target) {
+            (MessageTarget::User(MessageTargetUser { uid }), MessageTarget::User(other_target)) => {
+                BTreeSet::from([self.from_uid, uid])
+                    == BTreeSet::from([other.from_uid, other_target.uid])
+            }
+            (
+                MessageTarget::Group(MessageTargetGroup { gid }),
+                MessageTarget::Group(other_target),
+            ) => gid == other_target.gid,
+            _ => false,
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Copy, Clone, Debug, Hash, Eq, PartialEq)]
 pub enum MessageTarget {
@@ -82,13 +299,73 @@ pub struct MessageTargetGroup {
 pub enum MessageDetail {
     Normal(MessageNormal),
     Replay(MessageReplay),
+    /// 已撤回消息的墓碑，原内容不再保留
+    Recall(MessageRecall),
+    /// 合并转发的聊天记录
+    Merged(MessageMerged),
 }
 
 impl MessageDetail {
     pub fn get_content(&self) -> String {
         match self {
-            MessageDetail::Normal(msg) => msg.content.content.clone(),
-            MessageDetail::Replay(msg) => msg.content.content.clone(),
+            MessageDetail::Normal(msg) => msg.content.preview(),
+            MessageDetail::Replay(msg) => msg.content.preview(),
+            MessageDetail::Recall(_) => String::from("消息已撤回"),
+            MessageDetail::Merged(_) => String::from("[聊天记录]"),
+        }
+    }
+
+    /// 参与搜索的文本，已撤回与合并转发的消息不参与搜索
+    pub fn get_searchable_text(&self) -> Option<String> {
+        let content = match self {
+            MessageDetail::Normal(msg) => &msg.content,
+            MessageDetail::Replay(msg) => &msg.content,
+            MessageDetail::Recall(_) | MessageDetail::Merged(_) => return None,
+        };
+        let mut text = content.content.clone();
+        match &content.body {
+            Some(ContentBody::File(file)) => {
+                text.push(' ');
+                text.push_str(&file.name);
+            }
+            Some(ContentBody::Location(location)) => {
+                text.push(' ');
+                text.push_str(&location.title);
+                if let Some(address) = &location.address {
+                    text.push(' ');
+                    text.push_str(address);
+                }
+            }
+            _ => {}
+        }
+        Some(text)
+    }
+
+    /// 消息引用的附件id
+    pub fn get_attachment(&self) -> Option<i64> {
+        match self.get_body()? {
+            ContentBody::Image(ImageContent { attachment, .. })
+            | ContentBody::File(FileContent { attachment, .. })
+            | ContentBody::Audio(AudioContent { attachment, .. }) => attachment,
+            _ => None,
+        }
+    }
+
+    /// 非文本消息的内容
+    pub fn get_body(&self) -> Option<ContentBody> {
+        match self {
+            MessageDetail::Normal(msg) => msg.content.body.clone(),
+            MessageDetail::Replay(msg) => msg.content.body.clone(),
+            MessageDetail::Recall(_) | MessageDetail::Merged(_) => None,
+        }
+    }
+
+    /// 消息@的成员
+    pub fn get_mentions(&self) -> Option<&Mentions> {
+        match self {
+            MessageDetail::Normal(msg) => msg.content.mentions.as_ref(),
+            MessageDetail::Replay(msg) => msg.content.mentions.as_ref(),
+            MessageDetail::Recall(_) | MessageDetail::Merged(_) => None,
         }
     }
 }
@@ -104,77 +381,540 @@ pub struct MessageReplay {
     pub content: MessageContent,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MessageRecall {
+    /// 撤回人id，发送者本人或群管理员
+    pub recalled_by: i32,
+    /// 撤回时间
+    #[serde(with = "datetime_format")]
+    pub recalled_at: DateTime<Local>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MessageMerged {
+    /// 聊天记录快照id
+    pub id: i64,
+    /// 聊天记录中的消息数量
+    pub count: usize,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct MessageContent {
-    /// Extended attributes
-    // pub properties: Option<HashMap<String, Value>>,
-    /// Content type
-    // pub content_type: String,
-    /// Content
+    /// 文本内容，非文本消息时为附带的说明文字
+    #[serde(default)]
     pub(crate) content: String,
+    /// 非文本消息的内容，None为纯文本消息
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) body: Option<ContentBody>,
+    /// 群消息@的成员
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) mentions: Option<Mentions>,
+}
+
+/// 群消息@的成员
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Validate, ToSchema)]
+pub struct Mentions {
+    /// 被@的成员id
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[validate(length(max = 50, code = "1", message = "at most 50 members can be mentioned"))]
+    pub uids: Vec<i32>,
+    /// 是否@所有人，仅群管理员可用
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub all: bool,
+}
+
+impl MessageContent {
+    /// 消息摘要，非文本消息显示为类型标签
+    pub fn preview(&self) -> String {
+        match &self.body {
+            None => self.content.clone(),
+            Some(ContentBody::Image(_)) => String::from("[图片]"),
+            Some(ContentBody::File(file)) => format!("[文件] {}", file.name),
+            Some(ContentBody::Audio(_)) => String::from("[语音]"),
+            Some(ContentBody::Location(location)) => format!("[位置] {}", location.title),
+            Some(ContentBody::Card(_)) => String::from("[名片]"),
+            Some(ContentBody::Sticker(_)) => String::from("[表情]"),
+        }
+    }
+}
+
+/// 非文本消息的内容，按type区分类型
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentBody {
+    Image(ImageContent),
+    File(FileContent),
+    Audio(AudioContent),
+    Location(LocationContent),
+    Card(CardContent),
+    Sticker(StickerContent),
+}
+
+impl Validate for ContentBody {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        match self {
+            ContentBody::Image(image) => image.validate(),
+            ContentBody::File(file) => file.validate(),
+            ContentBody::Audio(audio) => audio.validate(),
+            ContentBody::Location(location) => location.validate(),
+            ContentBody::Card(card) => card.validate(),
+            ContentBody::Sticker(sticker) => sticker.validate(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate, ToSchema)]
+pub struct ImageContent {
+    #[validate(length(min = 1, max = 1024))]
+    pub url: String,
+    /// 上传的附件id，引用外部图片时为空
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachment: Option<i64>,
+    #[validate(range(min = 1))]
+    pub width: Option<u32>,
+    #[validate(range(min = 1))]
+    pub height: Option<u32>,
+    /// 缩略图，由服务端根据附件生成，按尺寸从小到大排列
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub thumbnails: Vec<Thumbnail>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
+pub struct Thumbnail {
+    /// 尺寸名称，如small、medium
+    pub size: String,
+    pub url: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate, ToSchema)]
+pub struct FileContent {
+    #[validate(length(min = 1, max = 1024))]
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachment: Option<i64>,
+    /// 文件名
+    #[validate(length(min = 1, max = 255))]
+    pub name: String,
+    /// 文件大小，单位字节
+    #[validate(range(min = 1))]
+    pub size: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate, ToSchema)]
+pub struct AudioContent {
+    #[validate(length(min = 1, max = 1024))]
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachment: Option<i64>,
+    /// 时长，单位秒
+    #[validate(range(min = 1, max = 600))]
+    pub duration: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Validate, ToSchema)]
+pub struct LocationContent {
+    #[validate(range(min = -90.0, max = 90.0))]
+    pub latitude: f64,
+    #[validate(range(min = -180.0, max = 180.0))]
+    pub longitude: f64,
+    /// 地点名称
+    #[validate(length(min = 1, max = 100))]
+    pub title: String,
+    /// 详细地址
+    #[validate(length(max = 255))]
+    pub address: Option<String>,
+}
+
+/// 名片，分享一个用户
+#[derive(Serialize, Deserialize, Clone, Debug, Validate, ToSchema)]
+pub struct CardContent {
+    #[validate(range(min = 1))]
+    pub uid: i32,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, Validate, ToSchema)]
+pub struct StickerContent {
+    /// 表情id
+    #[validate(length(min = 1, max = 64))]
+    pub id: String,
+    #[validate(length(min = 1, max = 1024))]
+    pub url: String,
+}
+
+/// 引用摘要的最大字符数
+const QUOTE_PREVIEW_LEN: usize = 50;
+
 /// Chat message
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct ChatMessage {
     /// Message id
     pub mid: i64,
     pub payload: ChatMessagePayload,
+    /// Snippet of the replied-to message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote: Option<Quote>,
 }
 
 impl ChatMessage {
     pub fn new(mid: i64, payload: ChatMessagePayload) -> Self {
-        ChatMessage { mid, payload }
+        ChatMessage {
+            mid,
+            payload,
+            quote: None,
+        }
     }
 }
 
+/// 被引用消息的摘要，读取时根据被引用消息的当前状态生成
+#[derive(Deserialize, Serialize, Clone, Debug, ToSchema)]
+pub struct Quote {
+    /// 被引用消息id
+    pub mid: i64,
+    /// 被引用消息的发送者id
+    pub from_uid: i32,
+    /// 被引用消息的内容摘要，已撤回时为"消息已撤回"
+    pub preview: String,
+}
+
+impl From<&ChatMessage> for Quote {
+    fn from(message: &ChatMessage) -> Self {
+        Quote {
+            mid: message.mid,
+            from_uid: message.payload.from_uid,
+            preview: message
+                .payload
+                .detail
+                .get_content()
+                .chars()
+                .take(QUOTE_PREVIEW_LEN)
+                .collect(),
+        }
+    }
+}
+
+/// 查询回复消息所引用消息的摘要，调用时不能持有msg_db的锁
+fn get_quote(payload: &ChatMessagePayload, app_state: &AppState) -> Option<Quote> {
+    match payload.detail {
+        MessageDetail::Replay(MessageReplay { mid, .. }) => {
+            get_by_mid(mid, app_state).as_ref().map(Quote::from)
+        }
+        _ => None,
+    }
+}
+
+/// 校验被引用的消息存在且与新消息属于同一会话
+fn check_quote(
+    mid: i64,
+    payload: &ChatMessagePayload,
+    app_state: &AppState,
+) -> Result<(), ServerError> {
+    let quoted = get_by_mid(mid, app_state).ok_or(MessageErr::MessageNotExist(mid))?;
+    if !quoted.payload.same_conversation(payload) {
+        return Err(MessageErr::QuoteNotInConversation(mid).into());
+    }
+    Ok(())
+}
+
 pub(crate) async fn send_msg(
-    payload: ChatMessagePayload,
+    mut payload: ChatMessagePayload,
     app_state: &AppState,
 ) -> Result<i64, ServerError> {
+    if let MessageDetail::Replay(MessageReplay { mid, .. }) = payload.detail {
+        check_quote(mid, &payload, app_state)?;
+    }
+    // 只能发送自己可以访问的附件
+    let attachment = payload.detail.get_attachment();
+    let model = match attachment {
+        Some(id) => {
+            let model = attachment::get_by_id(id, app_state).await?;
+            if !attachment::can_access(&model, payload.from_uid, app_state).await? {
+                return Err(AttachmentErr::NoPermission.into());
+            }
+            Some(model)
+        }
+        None => None,
+    };
+    // 图片的尺寸与缩略图以服务端记录的附件信息为准
+    if let MessageDetail::Normal(MessageNormal { content })
+    | MessageDetail::Replay(MessageReplay { content, .. }) = &mut payload.detail
+    {
+        if let Some(ContentBody::Image(image)) = &mut content.body {
+            match &model {
+                Some(model) => attachment::fill_image(image, model),
+                None => image.thumbnails.clear(),
+            }
+        }
+    }
     let from_uid = payload.from_uid;
+    let conversation = payload.conversation();
+    let text = payload.detail.get_searchable_text();
+    // 会话设置了消息保留时长时，消息到期后由sweep_expired删除
+    let ttl = app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .get_ttl(conversation)?;
+    payload.expire_at = ttl.map(|ttl| payload.created_at + Duration::from_secs(ttl as u64));
     let msg = serde_json::to_vec(&payload)
         .map_err(|_| ServerError::CustomErr("fail to serialize msg".to_string()))?;
     let mid = match payload.target {
         MessageTarget::User(MessageTargetUser { uid }) => {
-            let mid = app_state.msg_db.lock().unwrap().messages().send_to_dm(
-                from_uid as i64,
-                uid as i64,
-                &msg,
-            )?;
-            let _ = app_state.event_sender.send(Arc::new(BroadcastEvent::Chat {
+            if payload.detail.get_mentions().is_some() {
+                return Err(MessageErr::MentionOutsideGroup.into());
+            }
+            let mid = {
+                let msg_db = app_state.msg_db.lock().unwrap();
+                let mid = msg_db
+                    .messages()
+                    .send_to_dm(from_uid as i64, uid as i64, &msg)?;
+                let uids = vec![from_uid as i64, uid as i64];
+                expire_msg(&msg_db.messages(), mid, &payload, uids)?;
+                if let Some(id) = attachment {
+                    msg_db.messages().insert_attachment_ref(id, mid)?;
+                }
+                mid
+            };
+            app_state.hub.send(BroadcastEvent::Chat {
                 targets: BTreeSet::from([from_uid, uid]),
-                message: ChatMessage::new(mid, payload),
-            }));
+                message: build_quoted_message(mid, payload, app_state),
+            });
             mid
         }
         MessageTarget::Group(MessageTargetGroup { gid }) => {
             let uids = group::get_uids(&app_state, gid).await?;
-            let mid = app_state.msg_db.lock().unwrap().messages().send_to_group(
-                gid as i64,
-                uids.iter().map(|&x| i64::from(x)).collect::<Vec<i64>>(),
-                &msg,
-            )?;
-            let _ = app_state.event_sender.send(Arc::new(BroadcastEvent::Chat {
-                targets: uids.into_iter().collect(),
-                message: ChatMessage::new(mid, payload),
-            }));
+            let mentioned = resolve_mentions(&payload, gid, &uids, app_state).await?;
+            let mut to = uids.iter().map(|&x| i64::from(x)).collect::<Vec<i64>>();
+            let (mid, thread) = {
+                let msg_db = app_state.msg_db.lock().unwrap();
+                let (mid, thread) = match payload.thread {
+                    None => (
+                        msg_db
+                            .messages()
+                            .send_to_group(gid as i64, to.iter().copied(), &msg)?,
+                        None,
+                    ),
+                    // 与实时推送一致，话题回复只进入订阅者的收件箱，断线补发与增量同步不会发给其他成员
+                    Some(root) => {
+                        let subscribers = msg_db.messages().fetch_thread_subscribers(root)?;
+                        to.retain(|uid| subscribers.contains(uid));
+                        let (mid, stat) = msg_db.messages().send_to_thread(
+                            root,
+                            to.iter().copied(),
+                            &msg,
+                            payload.created_at.timestamp_millis(),
+                        )?;
+                        (mid, Some((root, stat, subscribers)))
+                    }
+                };
+                msg_db.messages().insert_mentions(
+                    gid as i64,
+                    mid,
+                    mentioned.into_iter().map(i64::from),
+                )?;
+                expire_msg(&msg_db.messages(), mid, &payload, to)?;
+                if let Some(id) = attachment {
+                    msg_db.messages().insert_attachment_ref(id, mid)?;
+                }
+                (mid, thread)
+            };
+            let target = payload.target;
+            let message = build_quoted_message(mid, payload, app_state);
+            match thread {
+                None => app_state.hub.send(BroadcastEvent::Chat {
+                    targets: uids.into_iter().collect(),
+                    message,
+                }),
+                // 话题回复只推送给话题的订阅者，其余成员只收到话题统计的变化
+                Some((root, stat, subscribers)) => {
+                    app_state.hub.send(BroadcastEvent::Chat {
+                        targets: uids
+                            .iter()
+                            .copied()
+                            .filter(|&uid| subscribers.contains(&(uid as i64)))
+                            .collect(),
+                        message,
+                    });
+                    app_state.hub.send(BroadcastEvent::Thread {
+                        targets: uids.into_iter().collect(),
+                        root,
+                        target,
+                        summary: stat.into(),
+                    });
+                }
+            }
             mid
         }
     };
+    update_index(mid, conversation, None, text, app_state)?;
     Ok(mid)
 }
 
+/// 以token对应的用户身份向好友或群发送消息
+pub(crate) async fn send_to(
+    target: MessageTarget,
+    detail: MessageDetail,
+    app_state: &AppState,
+    token: &Token,
+) -> Result<i64, ServerError> {
+    match target {
+        MessageTarget::User(MessageTargetUser { uid }) => {
+            user::send_to_friend(app_state, token, uid, detail).await
+        }
+        MessageTarget::Group(MessageTargetGroup { gid }) => {
+            group::send_to_group(app_state, token, gid, detail).await
+        }
+    }
+}
+
+/// 校验当前用户可以向目标发送消息
+pub(crate) async fn check_can_send(
+    target: MessageTarget,
+    app_state: &AppState,
+    token: &Token,
+) -> Result<(), ServerError> {
+    match target {
+        MessageTarget::User(MessageTargetUser { uid }) => {
+            user::check_can_send(uid, token, app_state).await
+        }
+        MessageTarget::Group(MessageTargetGroup { gid }) => {
+            group::check_can_send(gid, token.id, app_state).await
+        }
+    }
+}
+
+/// 会话中最新的消息
+fn fetch_latest(
+    messages: &Messages,
+    conversation: Conversation,
+) -> msg::Result<Option<(i64, Vec<u8>)>> {
+    let latest = match conversation {
+        Conversation::Dm(a, b) => messages.fetch_dm_messages_before(a, b, None, 1)?,
+        Conversation::Group(gid) => messages.fetch_group_messages_before(gid, None, 1)?,
+    };
+    Ok(latest.into_iter().next())
+}
+
+fn is_received(uid: i32, mid: i64, app_state: &AppState) -> Result<bool, ServerError> {
+    Ok(app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .is_received(uid as i64, mid)?)
+}
+
+/// 消息所在会话的当前成员
+async fn get_participants(
+    payload: &ChatMessagePayload,
+    app_state: &AppState,
+) -> Result<BTreeSet<i32>, ServerError> {
+    Ok(match payload.target {
+        MessageTarget::User(MessageTargetUser { uid }) => BTreeSet::from([payload.from_uid, uid]),
+        MessageTarget::Group(MessageTargetGroup { gid }) => {
+            group::get_uids(app_state, gid).await?.into_iter().collect()
+        }
+    })
+}
+
 pub enum HistoryMsgReq {
     User(HistoryMsgUser),
     Group(HistoryMsgGroup),
+    Thread(HistoryMsgThread),
+}
+
+/// 历史消息查询的起始位置
+#[derive(Clone, Copy, Debug)]
+pub enum HistoryCursor {
+    /// 查询before之前的消息，None时从最新的消息开始
+    Before(Option<i64>),
+    /// 查询after之后的消息
+    After(i64),
+    /// 定位到指定消息，查询该消息及其前后的消息
+    Around(i64),
 }
 
+#[derive(Clone, Copy, Debug)]
 pub struct HistoryReq {
-    pub(crate) before: Option<i64>,
+    pub(crate) cursor: HistoryCursor,
     pub(crate) limit: usize,
 }
 
+/// 历史消息默认每页数量
+const DEFAULT_HISTORY_LIMIT: usize = 50;
+
+/// 历史消息查询参数，before、after、around最多指定一个，都不指定时从最新的消息开始向前查询
+#[derive(Deserialize, Validate, Debug)]
+#[validate(schema(function = "validate_history_query"))]
+pub struct HistoryQuery {
+    /// 查询该消息之前的消息
+    pub before: Option<i64>,
+    /// 查询该消息之后的消息
+    pub after: Option<i64>,
+    /// 定位到该消息，返回该消息及其前后的消息
+    pub around: Option<i64>,
+    /// 每页数量，默认50，最大200
+    #[validate(range(min = 1, max = 200))]
+    pub limit: Option<usize>,
+}
+
+fn validate_history_query(query: &HistoryQuery) -> Result<(), ValidationError> {
+    let cursors = [query.before, query.after, query.around]
+        .iter()
+        .filter(|cursor| cursor.is_some())
+        .count();
+    if cursors > 1 {
+        return Err(ValidationError::new("1")
+            .with_message("only one of before, after and around is allowed".into()));
+    }
+    Ok(())
+}
+
+impl From<HistoryQuery> for HistoryReq {
+    fn from(query: HistoryQuery) -> Self {
+        let cursor = match (query.after, query.around) {
+            (Some(after), _) => HistoryCursor::After(after),
+            (_, Some(around)) => HistoryCursor::Around(around),
+            _ => HistoryCursor::Before(query.before),
+        };
+        HistoryReq {
+            cursor,
+            limit: query.limit.unwrap_or(DEFAULT_HISTORY_LIMIT),
+        }
+    }
+}
+
+/// 分页的历史消息，messages按消息id升序排列
+#[derive(Serialize, Debug, ToSchema)]
+#[aliases(
+    UserHistoryPage = HistoryPage<UserHistoryMsg>,
+    GroupHistoryPage = HistoryPage<GroupHistoryMsg>
+)]
+pub struct HistoryPage<T> {
+    pub messages: Vec<T>,
+    /// 继续查询的游标：before与around查询时为本页最早的消息id，用作下一页的before；
+    /// after查询时为本页最新的消息id，用作下一页的after。没有更多消息时为空
+    pub next_cursor: Option<i64>,
+    /// 查询方向上是否还有更多消息，around查询时指更早的消息
+    pub has_more: bool,
+    /// around查询时目标消息之后是否还有更新的消息，客户端用本页最新的消息id作为after向后翻页；
+    /// 其他查询时为false
+    pub has_newer: bool,
+}
+
+impl<T> HistoryPage<T> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> HistoryPage<U> {
+        HistoryPage {
+            messages: self.messages.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+            has_more: self.has_more,
+            has_newer: self.has_newer,
+        }
+    }
+}
+
 pub struct HistoryMsgUser {
     pub(crate) from_id: i32,
     pub(crate) to_id: i32,
@@ -186,59 +926,311 @@ pub struct HistoryMsgGroup {
     pub(crate) history: HistoryReq,
 }
 
+pub struct HistoryMsgThread {
+    pub(crate) root: i64,
+    pub(crate) history: HistoryReq,
+}
+
+/// 查询历史消息的用户，隐藏的消息及清空位置之前的消息对其不可见
+struct Viewer {
+    uid: i64,
+    cleared: i64,
+}
+
+impl Viewer {
+    fn visible(&self, messages: &Messages, mid: i64) -> msg::Result<bool> {
+        Ok(mid > self.cleared && !messages.is_hidden(self.uid, mid)?)
+    }
+}
+
+impl HistoryMsgReq {
+    fn history(&self) -> HistoryReq {
+        match self {
+            HistoryMsgReq::User(user) => user.history,
+            HistoryMsgReq::Group(group) => group.history,
+            HistoryMsgReq::Thread(thread) => thread.history,
+        }
+    }
+
+    /// 消息所属的会话，话题回复不单独清空
+    fn conversation(&self) -> Option<Conversation> {
+        match self {
+            HistoryMsgReq::User(HistoryMsgUser { from_id, to_id, .. }) => {
+                Some(Conversation::dm(*from_id as i64, *to_id as i64))
+            }
+            HistoryMsgReq::Group(HistoryMsgGroup { gid, .. }) => {
+                Some(Conversation::Group(*gid as i64))
+            }
+            HistoryMsgReq::Thread(_) => None,
+        }
+    }
+
+    /// 查询before之前对viewer可见的limit条消息，按消息id升序返回
+    fn fetch_visible_before(
+        &self,
+        messages: &Messages,
+        viewer: &Viewer,
+        mut before: Option<i64>,
+        limit: usize,
+    ) -> msg::Result<Vec<(i64, Vec<u8>)>> {
+        let mut msgs = Vec::new();
+        while msgs.len() < limit {
+            let batch = self.fetch_before(messages, before, limit)?;
+            let exhausted = batch.len() < limit;
+            let Some((first, _)) = batch.first() else {
+                break;
+            };
+            before = Some(*first);
+            let mut visible = Vec::with_capacity(batch.len());
+            for (mid, msg) in batch {
+                if viewer.visible(messages, mid)? {
+                    visible.push((mid, msg));
+                }
+            }
+            visible.append(&mut msgs);
+            msgs = visible;
+            if exhausted || before.is_some_and(|mid| mid <= viewer.cleared) {
+                break;
+            }
+        }
+        msgs.drain(..msgs.len().saturating_sub(limit));
+        Ok(msgs)
+    }
+
+    /// 查询after之后对viewer可见的limit条消息，按消息id升序返回
+    fn fetch_visible_after(
+        &self,
+        messages: &Messages,
+        viewer: &Viewer,
+        after: i64,
+        limit: usize,
+    ) -> msg::Result<Vec<(i64, Vec<u8>)>> {
+        let mut after = after.max(viewer.cleared);
+        let mut msgs = Vec::new();
+        while msgs.len() < limit {
+            let batch = self.fetch_after(messages, after, limit)?;
+            let exhausted = batch.len() < limit;
+            let Some((last, _)) = batch.last() else {
+                break;
+            };
+            after = *last;
+            for (mid, msg) in batch {
+                if viewer.visible(messages, mid)? {
+                    msgs.push((mid, msg));
+                }
+            }
+            if exhausted {
+                break;
+            }
+        }
+        msgs.truncate(limit);
+        Ok(msgs)
+    }
+
+    fn fetch_before(
+        &self,
+        messages: &Messages,
+        before: Option<i64>,
+        limit: usize,
+    ) -> msg::Result<Vec<(i64, Vec<u8>)>> {
+        match self {
+            HistoryMsgReq::User(HistoryMsgUser { from_id, to_id, .. }) => {
+                messages.fetch_dm_messages_before(*from_id as i64, *to_id as i64, before, limit)
+            }
+            HistoryMsgReq::Group(HistoryMsgGroup { gid, .. }) => {
+                messages.fetch_group_messages_before(*gid as i64, before, limit)
+            }
+            HistoryMsgReq::Thread(HistoryMsgThread { root, .. }) => {
+                messages.fetch_thread_messages_before(*root, before, limit)
+            }
+        }
+    }
+
+    fn fetch_after(
+        &self,
+        messages: &Messages,
+        after: i64,
+        limit: usize,
+    ) -> msg::Result<Vec<(i64, Vec<u8>)>> {
+        match self {
+            HistoryMsgReq::User(HistoryMsgUser { from_id, to_id, .. }) => {
+                messages.fetch_dm_messages_after(*from_id as i64, *to_id as i64, after, limit)
+            }
+            HistoryMsgReq::Group(HistoryMsgGroup { gid, .. }) => {
+                messages.fetch_group_messages_after(*gid as i64, after, limit)
+            }
+            HistoryMsgReq::Thread(HistoryMsgThread { root, .. }) => {
+                messages.fetch_thread_messages_after(*root, after, limit)
+            }
+        }
+    }
+}
+
+/// 分页查询uid可见的会话历史消息，多查询一条用于判断是否还有更多消息
 pub(crate) fn get_history_msg(
     app_state: &AppState,
+    uid: i32,
     history_msg_req: HistoryMsgReq,
-) -> Vec<ChatMessage> {
-    match history_msg_req {
-        HistoryMsgReq::User(HistoryMsgUser {
-            from_id,
-            to_id,
-            history: HistoryReq { before, limit },
-        }) => {
-            let result = app_state
-                .msg_db
-                .lock()
-                .unwrap()
-                .messages()
-                .fetch_dm_messages_before(from_id as i64, to_id as i64, before, limit)
-                .ok();
-            match result {
-                Some(msgs) => build_chat_messages(msgs),
-                None => vec![],
+) -> Result<HistoryPage<ChatMessage>, ServerError> {
+    let HistoryReq { cursor, limit } = history_msg_req.history();
+    let (msgs, has_more, has_newer) = {
+        let msg_db = app_state.msg_db.lock().unwrap();
+        let messages = msg_db.messages();
+        let cleared = match history_msg_req.conversation() {
+            Some(conversation) => messages.get_cleared(uid as i64, conversation)?,
+            None => None,
+        };
+        let viewer = Viewer {
+            uid: uid as i64,
+            cleared: cleared.unwrap_or_default(),
+        };
+        let req = &history_msg_req;
+        match cursor {
+            HistoryCursor::Before(before) => {
+                let mut msgs = req.fetch_visible_before(&messages, &viewer, before, limit + 1)?;
+                let has_more = msgs.len() > limit;
+                if has_more {
+                    msgs.remove(0);
+                }
+                (msgs, has_more, false)
+            }
+            HistoryCursor::After(after) => {
+                let mut msgs = req.fetch_visible_after(&messages, &viewer, after, limit + 1)?;
+                let has_more = msgs.len() > limit;
+                msgs.truncate(limit);
+                (msgs, has_more, false)
+            }
+            HistoryCursor::Around(mid) => {
+                // 目标消息及其之前的消息占一半
+                let half = limit.div_ceil(2);
+                let mut msgs = req.fetch_visible_before(
+                    &messages,
+                    &viewer,
+                    Some(mid.saturating_add(1)),
+                    half + 1,
+                )?;
+                let has_more = msgs.len() > half;
+                if has_more {
+                    msgs.remove(0);
+                }
+                let newer = limit - half;
+                let mut newer_msgs = req.fetch_visible_after(&messages, &viewer, mid, newer + 1)?;
+                let has_newer = newer_msgs.len() > newer;
+                newer_msgs.truncate(newer);
+                msgs.extend(newer_msgs);
+                (msgs, has_more, has_newer)
             }
         }
-        HistoryMsgReq::Group(HistoryMsgGroup {
-            gid,
-            history: HistoryReq { before, limit },
-        }) => {
-            let result = app_state
-                .msg_db
-                .lock()
-                .unwrap()
-                .messages()
-                .fetch_group_messages_before(gid as i64, before, limit)
-                .ok();
-            match result {
-                Some(msgs) => build_chat_messages(msgs),
-                None => vec![],
+    };
+    let next_cursor = match (has_more, cursor) {
+        (false, _) => None,
+        (true, HistoryCursor::After(_)) => msgs.last().map(|(mid, _)| *mid),
+        (true, _) => msgs.first().map(|(mid, _)| *mid),
+    };
+    Ok(HistoryPage {
+        messages: build_quoted_messages(msgs, app_state),
+        next_cursor,
+        has_more,
+        has_newer,
+    })
+}
+
+/// 查询用户after之后的limit条消息（包括单聊和群聊），按消息id升序返回
+pub(crate) fn get_user_msg_after(
+    app_state: &AppState,
+    uid: i32,
+    after: Option<i64>,
+    limit: usize,
+) -> Result<Vec<ChatMessage>, ServerError> {
+    let msgs = app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .fetch_user_messages_after(uid as i64, after, limit)?;
+    Ok(build_quoted_messages(msgs, app_state))
+}
+
+/// 查询after之后用户收到的最多limit条消息，过滤掉用户隐藏的消息及已清空会话中的消息，
+/// 同时返回扫描到的最后一条消息id（可能对用户不可见）以及之后是否可能还有消息
+pub(crate) fn get_user_visible_msg_after(
+    app_state: &AppState,
+    uid: i32,
+    after: Option<i64>,
+    limit: usize,
+) -> Result<(Vec<ChatMessage>, Option<i64>, bool), ServerError> {
+    let (visible, last_mid, has_more) = {
+        let msg_db = app_state.msg_db.lock().unwrap();
+        let messages = msg_db.messages();
+        let msgs = messages.fetch_user_messages_after(uid as i64, after, limit)?;
+        let last_mid = msgs.last().map(|(mid, _)| *mid);
+        let has_more = msgs.len() >= limit;
+        let mut conversation_2_cleared = HashMap::new();
+        let mut visible = Vec::with_capacity(msgs.len());
+        for (mid, msg) in msgs {
+            let Some(message) = build_chat_message(mid, msg) else {
+                continue;
+            };
+            let conversation = message.payload.conversation();
+            let cleared = match conversation_2_cleared.get(&conversation) {
+                Some(&cleared) => cleared,
+                None => {
+                    let cleared = messages
+                        .get_cleared(uid as i64, conversation)?
+                        .unwrap_or_default();
+                    conversation_2_cleared.insert(conversation, cleared);
+                    cleared
+                }
+            };
+            if mid > cleared && !messages.is_hidden(uid as i64, mid)? {
+                visible.push(message);
             }
         }
-    }
+        (visible, last_mid, has_more)
+    };
+    let visible = visible
+        .into_iter()
+        .map(|message| build_quoted_message(message.mid, message.payload, app_state))
+        .collect();
+    Ok((visible, last_mid, has_more))
 }
 
-fn build_chat_messages(msgs: Vec<(i64, Vec<u8>)>) -> Vec<ChatMessage> {
+fn build_quoted_messages(msgs: Vec<(i64, Vec<u8>)>, app_state: &AppState) -> Vec<ChatMessage> {
     msgs.into_iter()
         .filter_map(|(mid, msg)| build_chat_message(mid, msg))
+        .map(|message| build_quoted_message(message.mid, message.payload, app_state))
         .collect()
 }
 
+fn build_quoted_message(
+    mid: i64,
+    payload: ChatMessagePayload,
+    app_state: &AppState,
+) -> ChatMessage {
+    ChatMessage {
+        quote: get_quote(&payload, app_state),
+        ..ChatMessage::new(mid, payload)
+    }
+}
+
 fn build_chat_message(mid: i64, msg: Vec<u8>) -> Option<ChatMessage> {
     serde_json::from_slice::<ChatMessagePayload>(&msg)
         .ok()
         .map(|c| ChatMessage::new(mid, c))
 }
 
+pub(crate) fn get_by_mid(mid: i64, app_state: &AppState) -> Option<ChatMessage> {
+    app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .get(mid)
+        .ok()
+        .flatten()
+        .and_then(|msg| build_chat_message(mid, msg))
+}
+
 pub(crate) fn get_by_mids(mids: Vec<i64>, app_state: &AppState) -> Vec<ChatMessage> {
     mids.into_iter()
         .filter_map(|mid| {
@@ -315,3 +1307,57 @@ pub(crate) fn count_dm_unread(
         },
     }
 }
+
+#[cfg(test)]
+mod test {
+    use validator::Validate;
+
+    use crate::message::{
+        ChatMessagePayload, MessageContent, MessageDetail, MessageNormal, SendMsgReq,
+    };
+
+    #[test]
+    fn deserialize_stored_payload() {
+        // 早期存储的消息没有edited_at等字段
+        let stored = r#"{"from_uid":1,"created_at":"2024-09-17 10:00:00","target":{"User":{"uid":2}},"detail":{"Normal":{"content":{"content":"hello"}}}}"#;
+        let payload = serde_json::from_str::<ChatMessagePayload>(stored).unwrap();
+        assert!(payload.edited_at.is_none());
+        assert_eq!(payload.detail.get_content(), "hello");
+        assert!(payload.detail.get_body().is_none());
+
+        let payload =
+            serde_json::from_slice::<ChatMessagePayload>(&serde_json::to_vec(&payload).unwrap())
+                .unwrap();
+        assert!(matches!(payload.detail, MessageDetail::Normal(_)));
+        assert!(payload.edited_at.is_none());
+    }
+
+    #[test]
+    fn validate_typed_content() {
+        let req = serde_json::from_str::<SendMsgReq>(
+            r#"{"body":{"type":"image","url":"/attachment/1","width":640,"height":480}}"#,
+        )
+        .unwrap();
+        assert!(req.validate().is_ok());
+        assert_eq!(
+            MessageDetail::Normal(MessageNormal {
+                content: MessageContent {
+                    content: req.msg,
+                    body: req.body,
+                    mentions: None,
+                },
+            })
+            .get_content(),
+            "[图片]"
+        );
+
+        let req = serde_json::from_str::<SendMsgReq>(
+            r#"{"body":{"type":"location","latitude":91.0,"longitude":0.0,"title":"somewhere"}}"#,
+        )
+        .unwrap();
+        assert!(req.validate().is_err());
+
+        let req = serde_json::from_str::<SendMsgReq>(r#"{"msg":""}"#).unwrap();
+        assert!(req.validate().is_err());
+    }
+}
@@ -0,0 +1,62 @@
+use crate::app_state::AppState;
+use crate::auth::Token;
+use crate::err::ServerError;
+use crate::message::{send_to, MessageErr, MessageTarget, SendMsgReq};
+use chrono::Local;
+use msg::ClientKey;
+use std::time::Duration;
+use tracing::warn;
+
+/// 客户端去重键的有效期，有效期内以相同的键重试发送时返回首次发送的消息id
+const CLIENT_KEY_WINDOW: Duration = Duration::from_secs(24 * 3600);
+
+/// 去重键的发送租期，超过租期仍未记录消息id时视为发送已中断（如服务重启），允许以该键重新发送
+const CLIENT_KEY_LEASE: Duration = Duration::from_secs(30);
+
+/// 发送消息，请求带有去重键时同一发送者的重复请求只发送一次
+pub(crate) async fn send_once(
+    target: MessageTarget,
+    msg: SendMsgReq,
+    app_state: &AppState,
+    token: &Token,
+) -> Result<i64, ServerError> {
+    let Some(client_key) = msg.client_key.clone() else {
+        return send_to(target, msg.into_detail(), app_state, token).await;
+    };
+    let uid = token.id as i64;
+    let now = Local::now().timestamp_millis();
+    let reserved = app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .reserve_client_key(
+            uid,
+            &client_key,
+            now,
+            now - CLIENT_KEY_WINDOW.as_millis() as i64,
+            now - CLIENT_KEY_LEASE.as_millis() as i64,
+        )?;
+    match reserved {
+        ClientKey::Sent(mid) => return Ok(mid),
+        ClientKey::Pending => return Err(MessageErr::SendInProgress(client_key).into()),
+        ClientKey::Reserved => {}
+    }
+    // 在独立的任务中发送并记录结果，客户端超时断开导致请求被取消时去重键仍能记录消息id
+    let app_state = app_state.clone();
+    let token = token.clone();
+    tokio::spawn(async move {
+        let result = send_to(target, msg.into_detail(), &app_state, &token).await;
+        let msg_db = app_state.msg_db.lock().unwrap();
+        let recorded = match result {
+            Ok(mid) => msg_db.messages().set_client_key_mid(uid, &client_key, mid),
+            Err(_) => msg_db.messages().release_client_key(uid, &client_key),
+        };
+        if let Err(err) = recorded {
+            warn!("fail to record client key {client_key} of user {uid}: {err}");
+        }
+        result
+    })
+    .await
+    .map_err(|err| ServerError::CustomErr(err.to_string()))?
+}
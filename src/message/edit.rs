@@ -0,0 +1,213 @@
+use crate::app_state::AppState;
+use crate::auth::Token;
+use crate::err::ServerError;
+use crate::event::BroadcastEvent;
+use crate::message::{
+    build_quoted_message, get_by_mid, get_participants, update_index, ChatMessagePayload,
+    MessageContent, MessageDetail, MessageErr, MessageMerged, MessageNormal, MessageRecall,
+    MessageReplay, MessageTarget, MessageTargetGroup, MessageTargetUser,
+};
+use crate::validate::ValidatedJson;
+use crate::{group, Res};
+use axum::extract::{Path, State};
+use chrono::Local;
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::env;
+use std::sync::LazyLock;
+use std::time::Duration;
+use utoipa::ToSchema;
+use validator::Validate;
+
+/// 消息撤回的时间窗口，默认2分钟
+static RECALL_WINDOW: LazyLock<Duration> = LazyLock::new(|| {
+    Duration::from_secs(
+        env::var("RECALL_WINDOW_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(120),
+    )
+});
+
+/// Edit message request
+#[derive(Deserialize, Validate, Debug, ToSchema)]
+pub struct EditMsgReq {
+    /// New message content
+    #[validate(length(min = 1, code = "1", message = "msg is blank"))]
+    pub msg: String,
+}
+
+/// 撤回消息
+pub(super) async fn recall(
+    State(app_state): State<AppState>,
+    Path(mid): Path<i64>,
+    token: Token,
+) -> Res<()> {
+    let message = get_by_mid(mid, &app_state).ok_or(MessageErr::MessageNotExist(mid))?;
+    if let MessageDetail::Recall(_) = message.payload.detail {
+        return Err(MessageErr::MessageRecalled.into());
+    }
+    // 发送者本人或群管理员可以撤回
+    let permitted = message.payload.from_uid == token.id
+        || match message.payload.target {
+            MessageTarget::User(_) => false,
+            MessageTarget::Group(MessageTargetGroup { gid }) => {
+                group::is_admin(gid, token.id, &app_state).await?
+            }
+        };
+    if !permitted {
+        return Err(MessageErr::NoPermission.into());
+    }
+    if message.payload.created_at + *RECALL_WINDOW < Local::now() {
+        return Err(MessageErr::RecallTimeout(RECALL_WINDOW.as_secs()).into());
+    }
+    let text = message.payload.detail.get_searchable_text();
+    let payload = ChatMessagePayload {
+        detail: MessageDetail::Recall(MessageRecall {
+            recalled_by: token.id,
+            recalled_at: Local::now(),
+        }),
+        ..message.payload
+    };
+    let targets = replace_msg(mid, &payload, &app_state).await?;
+    update_index(mid, payload.conversation(), text, None, &app_state)?;
+    {
+        let msg_db = app_state.msg_db.lock().unwrap();
+        msg_db.messages().remove_reactions(mid)?;
+        msg_db.messages().unpin_msg(payload.conversation(), mid)?;
+        // 删除被@用户的提醒记录，@所有人时包括全部群成员，也包括已退群的被@用户
+        if let MessageTarget::Group(MessageTargetGroup { gid }) = payload.target {
+            let mentioned = message.payload.detail.get_mentions().map(|mentions| {
+                targets
+                    .iter()
+                    .chain(&mentions.uids)
+                    .map(|&uid| i64::from(uid))
+                    .collect::<BTreeSet<i64>>()
+            });
+            if let Some(mentioned) = mentioned {
+                msg_db
+                    .messages()
+                    .remove_mentions(gid as i64, mid, mentioned)?;
+            }
+        }
+        if let MessageDetail::Merged(MessageMerged { id, .. }) = message.payload.detail {
+            msg_db.messages().remove_merged_msg(id)?;
+        }
+    }
+    app_state.hub.send(BroadcastEvent::Recall {
+        targets,
+        mid,
+        target: payload.target,
+        recalled_by: token.id,
+    });
+    Ok(())
+}
+
+/// 编辑消息，仅发送者本人可以编辑，编辑前的版本保存为历史版本
+pub(super) async fn edit(
+    State(app_state): State<AppState>,
+    Path(mid): Path<i64>,
+    token: Token,
+    ValidatedJson(req): ValidatedJson<EditMsgReq>,
+) -> Res<()> {
+    let message = get_by_mid(mid, &app_state).ok_or(MessageErr::MessageNotExist(mid))?;
+    if message.payload.from_uid != token.id {
+        return Err(MessageErr::NoPermission.into());
+    }
+    // 非文本消息只能编辑说明文字
+    let detail = match &message.payload.detail {
+        MessageDetail::Normal(normal) => MessageDetail::Normal(MessageNormal {
+            content: MessageContent {
+                content: req.msg,
+                body: normal.content.body.clone(),
+                mentions: normal.content.mentions.clone(),
+            },
+        }),
+        MessageDetail::Replay(replay) => MessageDetail::Replay(MessageReplay {
+            mid: replay.mid,
+            content: MessageContent {
+                content: req.msg,
+                body: replay.content.body.clone(),
+                mentions: replay.content.mentions.clone(),
+            },
+        }),
+        MessageDetail::Recall(_) => return Err(MessageErr::MessageRecalled.into()),
+        MessageDetail::Merged(_) => return Err(MessageErr::NotEditable.into()),
+    };
+    let old_text = message.payload.detail.get_searchable_text();
+    let revision = serde_json::to_vec(&message.payload)
+        .map_err(|_| ServerError::CustomErr("fail to serialize msg".to_string()))?;
+    app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .insert_revision(mid, &revision)?;
+    let payload = ChatMessagePayload {
+        detail,
+        edited_at: Some(Local::now()),
+        ..message.payload
+    };
+    let targets = replace_msg(mid, &payload, &app_state).await?;
+    update_index(
+        mid,
+        payload.conversation(),
+        old_text,
+        payload.detail.get_searchable_text(),
+        &app_state,
+    )?;
+    app_state.hub.send(BroadcastEvent::Edit {
+        targets,
+        message: build_quoted_message(mid, payload, &app_state),
+    });
+    Ok(())
+}
+
+/// 查询消息的历史版本，按编辑顺序返回
+pub(crate) fn get_revisions(
+    mid: i64,
+    app_state: &AppState,
+) -> Result<Vec<ChatMessagePayload>, ServerError> {
+    let revisions = app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .fetch_revisions(mid)?;
+    Ok(revisions
+        .into_iter()
+        .filter_map(|msg| serde_json::from_slice::<ChatMessagePayload>(&msg).ok())
+        .collect())
+}
+
+/// 覆盖已存储的消息，返回可以看到该消息的用户
+async fn replace_msg(
+    mid: i64,
+    payload: &ChatMessagePayload,
+    app_state: &AppState,
+) -> Result<BTreeSet<i32>, ServerError> {
+    let msg = serde_json::to_vec(payload)
+        .map_err(|_| ServerError::CustomErr("fail to serialize msg".to_string()))?;
+    let targets = get_participants(payload, app_state).await?;
+    let to = targets.iter().map(|&x| i64::from(x)).collect::<Vec<i64>>();
+    match payload.target {
+        MessageTarget::User(MessageTargetUser { uid }) => {
+            app_state.msg_db.lock().unwrap().messages().replace_dm_msg(
+                payload.from_uid as i64,
+                uid as i64,
+                mid,
+                &msg,
+            )?;
+        }
+        MessageTarget::Group(MessageTargetGroup { gid }) => {
+            let msg_db = app_state.msg_db.lock().unwrap();
+            match payload.thread {
+                None => msg_db
+                    .messages()
+                    .replace_group_msg(gid as i64, to, mid, &msg)?,
+                Some(root) => msg_db.messages().replace_thread_msg(root, to, mid, &msg)?,
+            }
+        }
+    }
+    Ok(targets)
+}
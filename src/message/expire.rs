@@ -0,0 +1,167 @@
+use crate::app_state::AppState;
+use crate::attachment;
+use crate::auth::Token;
+use crate::err::ServerError;
+use crate::event::BroadcastEvent;
+use crate::message::{
+    build_chat_message, fetch_latest, get_by_mid, get_merged_messages, update_index,
+    ChatMessagePayload, MessageDetail, MessageMerged, MessageTarget, MessageTargetGroup,
+    MessageTargetUser,
+};
+use crate::{group, read_index};
+use chrono::Local;
+use msg::{Conversation, Expiry, Messages};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+use tracing::warn;
+use utoipa::ToSchema;
+use validator::Validate;
+
+/// 登记会到期的消息，uids为收到该消息的用户
+pub(super) fn expire_msg(
+    messages: &Messages,
+    mid: i64,
+    payload: &ChatMessagePayload,
+    uids: Vec<i64>,
+) -> Result<(), ServerError> {
+    if let Some(expire_at) = payload.expire_at {
+        messages.expire_msg(&Expiry {
+            mid,
+            expire_at: expire_at.timestamp_millis(),
+            conversation: payload.conversation(),
+            thread: payload.thread,
+            uids,
+        })?;
+    }
+    Ok(())
+}
+
+/// 会话的消息保留时长
+#[derive(Serialize, Deserialize, Validate, Debug, ToSchema)]
+pub struct Ttl {
+    /// 消息保留时长（秒），为空表示消息不过期
+    #[validate(range(
+        min = 5,
+        max = 2592000,
+        code = "1",
+        message = "ttl should be 5 seconds to 30 days"
+    ))]
+    pub ttl: Option<u64>,
+}
+
+/// 清理到期消息的间隔
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 每次最多清理的到期消息数量
+const SWEEP_BATCH: usize = 100;
+
+/// 查询会话的消息保留时长，调用方需校验用户可以查看该会话
+pub(crate) fn get_ttl(
+    conversation: Conversation,
+    app_state: &AppState,
+) -> Result<Ttl, ServerError> {
+    let ttl = app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .get_ttl(conversation)?;
+    Ok(Ttl {
+        ttl: ttl.map(|ttl| ttl as u64),
+    })
+}
+
+/// 设置会话的消息保留时长，只对之后发送的消息生效，调用方需校验权限
+pub(crate) async fn set_ttl(
+    target: MessageTarget,
+    ttl: Ttl,
+    app_state: &AppState,
+    token: &Token,
+) -> Result<(), ServerError> {
+    let (conversation, targets) = match target {
+        MessageTarget::User(MessageTargetUser { uid }) => (
+            Conversation::dm(token.id as i64, uid as i64),
+            BTreeSet::from([token.id, uid]),
+        ),
+        MessageTarget::Group(MessageTargetGroup { gid }) => (
+            Conversation::Group(gid as i64),
+            group::get_uids(app_state, gid).await?.into_iter().collect(),
+        ),
+    };
+    app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .set_ttl(conversation, ttl.ttl.map(|ttl| ttl as i64))?;
+    app_state.hub.send(BroadcastEvent::Ttl {
+        targets,
+        target,
+        ttl: ttl.ttl,
+        operator: token.id,
+    });
+    Ok(())
+}
+
+/// 后台删除到期的消息，并修正指向已删除消息的read_index
+pub async fn sweep_expired(app_state: AppState) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(err) = remove_expired(&app_state).await {
+            warn!("fail to remove expired messages: {err}");
+        }
+    }
+}
+
+async fn remove_expired(app_state: &AppState) -> Result<(), ServerError> {
+    let expired = app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .fetch_expired(Local::now().timestamp_millis(), SWEEP_BATCH)?;
+    let mut removed: HashMap<Conversation, Vec<i64>> = HashMap::new();
+    for expiry in expired {
+        let mut merged = None;
+        let mut attachments = vec![];
+        if let Some(message) = get_by_mid(expiry.mid, app_state) {
+            let text = message.payload.detail.get_searchable_text();
+            update_index(expiry.mid, expiry.conversation, text, None, app_state)?;
+            attachments.extend(message.payload.detail.get_attachment());
+            // 合并转发的聊天记录中的附件引用记在合并转发消息上
+            if let MessageDetail::Merged(MessageMerged { id, .. }) = message.payload.detail {
+                merged = Some(id);
+                attachments.extend(
+                    get_merged_messages(id, app_state)?
+                        .iter()
+                        .filter_map(|message| message.payload.detail.get_attachment()),
+                );
+            }
+        }
+        app_state.msg_db.lock().unwrap().messages().remove_expired(
+            &expiry,
+            merged,
+            &attachments,
+        )?;
+        for id in attachments {
+            attachment::remove_unreferenced(id, app_state).await?;
+        }
+        // 话题回复不会成为会话的最新消息
+        if expiry.thread.is_none() {
+            removed
+                .entry(expiry.conversation)
+                .or_default()
+                .push(expiry.mid);
+        }
+    }
+    for (conversation, mids) in removed {
+        let latest = fetch_latest(&app_state.msg_db.lock().unwrap().messages(), conversation)?;
+        let latest = latest
+            .and_then(|(mid, msg)| build_chat_message(mid, msg))
+            .map(|message| (message.mid, message.payload.from_uid));
+        read_index::reset_latest_mid(conversation, mids, latest, app_state).await?;
+    }
+    Ok(())
+}
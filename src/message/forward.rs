@@ -0,0 +1,123 @@
+use crate::app_state::AppState;
+use crate::auth::Token;
+use crate::err::ServerError;
+use crate::message::{
+    build_quoted_message, get_by_mid, is_received, send_to, ChatMessage, MessageDetail, MessageErr,
+    MessageMerged, MessageTarget,
+};
+use crate::validate::ValidatedJson;
+use crate::Res;
+use axum::extract::{Path, State};
+use axum::Json;
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use validator::Validate;
+
+/// Forward messages as a bundle request
+#[derive(Deserialize, Validate, Debug)]
+pub struct ForwardReq {
+    /// Ids of the messages to forward, must be in the same conversation
+    #[validate(length(min = 1, max = 100, code = "1", message = "mids should be 1 to 100"))]
+    pub mids: Vec<i64>,
+    /// Forward target
+    pub target: MessageTarget,
+}
+
+/// 合并转发同一会话中的多条消息，消息快照保存后作为一条聊天记录消息发送给好友或群
+pub(super) async fn forward(
+    State(app_state): State<AppState>,
+    token: Token,
+    ValidatedJson(req): ValidatedJson<ForwardReq>,
+) -> Res<String> {
+    let mut messages: Vec<ChatMessage> = vec![];
+    for mid in req.mids.into_iter().collect::<BTreeSet<i64>>() {
+        let message = get_by_mid(mid, &app_state).ok_or(MessageErr::MessageNotExist(mid))?;
+        if !is_received(token.id, mid, &app_state)? {
+            return Err(MessageErr::NoPermission.into());
+        }
+        if let MessageDetail::Recall(_) = message.payload.detail {
+            return Err(MessageErr::MessageRecalled.into());
+        }
+        if let Some(first) = messages.first() {
+            if !first.payload.same_conversation(&message.payload) {
+                return Err(MessageErr::ForwardAcrossConversations.into());
+            }
+        }
+        messages.push(build_quoted_message(mid, message.payload, &app_state));
+    }
+    let snapshot = serde_json::to_vec(&messages)
+        .map_err(|_| ServerError::CustomErr("fail to serialize msg".to_string()))?;
+    // 先保存快照再发送，保证收到消息时即可展开
+    let id = {
+        let msg_db = app_state.msg_db.lock().unwrap();
+        let id = msg_db.messages().generate_id()?;
+        msg_db.messages().insert_merged_msg(id, &snapshot)?;
+        id
+    };
+    let detail = MessageDetail::Merged(MessageMerged {
+        id,
+        count: messages.len(),
+    });
+    let result = send_to(req.target, detail, &app_state, &token).await;
+    let msg_db = app_state.msg_db.lock().unwrap();
+    match result {
+        Ok(mid) => {
+            // 聊天记录的接收者可以下载其中的附件
+            for message in &messages {
+                if let Some(attachment) = message.payload.detail.get_attachment() {
+                    msg_db.messages().insert_attachment_ref(attachment, mid)?;
+                }
+            }
+            Ok(mid.to_string())
+        }
+        Err(err) => {
+            msg_db.messages().remove_merged_msg(id)?;
+            Err(err)
+        }
+    }
+}
+
+/// 展开合并转发的聊天记录，仅收到该聊天记录的用户可以查看
+pub(super) async fn merged(
+    State(app_state): State<AppState>,
+    Path(mid): Path<i64>,
+    token: Token,
+) -> Res<Json<Vec<ChatMessage>>> {
+    let message = get_by_mid(mid, &app_state).ok_or(MessageErr::MessageNotExist(mid))?;
+    if !is_received(token.id, mid, &app_state)? {
+        return Err(MessageErr::NoPermission.into());
+    }
+    let id = match message.payload.detail {
+        MessageDetail::Merged(MessageMerged { id, .. }) => id,
+        MessageDetail::Recall(_) => return Err(MessageErr::MessageRecalled.into()),
+        _ => return Err(MessageErr::NotMerged(mid).into()),
+    };
+    let snapshot = app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .get_merged_msg(id)?
+        .ok_or(MessageErr::MessageNotExist(mid))?;
+    let messages = serde_json::from_slice::<Vec<ChatMessage>>(&snapshot)
+        .map_err(|_| ServerError::CustomErr("fail to deserialize msg".to_string()))?;
+    Ok(Json(messages))
+}
+
+/// 读取合并转发的聊天记录，聊天记录不存在时返回空
+pub(super) fn get_merged_messages(
+    id: i64,
+    app_state: &AppState,
+) -> Result<Vec<ChatMessage>, ServerError> {
+    let Some(snapshot) = app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .get_merged_msg(id)?
+    else {
+        return Ok(vec![]);
+    };
+    serde_json::from_slice(&snapshot)
+        .map_err(|_| ServerError::CustomErr("fail to deserialize msg".to_string()))
+}
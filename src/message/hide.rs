@@ -0,0 +1,82 @@
+use crate::app_state::AppState;
+use crate::auth::Token;
+use crate::err::ServerError;
+use crate::message::{
+    fetch_latest, get_history_msg, is_received, ChatMessage, HistoryCursor, HistoryMsgGroup,
+    HistoryMsgReq, HistoryMsgUser, HistoryReq, MessageErr,
+};
+use crate::Res;
+use axum::extract::{Path, State};
+use msg::Conversation;
+
+/// 删除消息，只对自己隐藏该消息，不影响会话中的其他用户
+pub(super) async fn hide(
+    State(app_state): State<AppState>,
+    Path(mid): Path<i64>,
+    token: Token,
+) -> Res<()> {
+    if !is_received(token.id, mid, &app_state)? {
+        return Err(MessageErr::MessageNotExist(mid).into());
+    }
+    app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .hide_msg(token.id as i64, mid)?;
+    Ok(())
+}
+
+/// 清空会话，会话中现有的消息对该用户不可见，不影响会话中的其他用户
+pub(crate) fn clear_conversation(
+    uid: i32,
+    conversation: Conversation,
+    app_state: &AppState,
+) -> Result<(), ServerError> {
+    let msg_db = app_state.msg_db.lock().unwrap();
+    if let Some((mid, _)) = fetch_latest(&msg_db.messages(), conversation)? {
+        msg_db
+            .messages()
+            .clear_conversation(uid as i64, conversation, mid)?;
+    }
+    Ok(())
+}
+
+/// 查询用户会话的清空位置，该位置及之前的消息对用户不可见
+pub(crate) fn get_cleared(
+    uid: i32,
+    conversation: Conversation,
+    app_state: &AppState,
+) -> Result<Option<i64>, ServerError> {
+    Ok(app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .get_cleared(uid as i64, conversation)?)
+}
+
+/// 查询会话中latest_mid及之前用户可见的最新消息，会话列表的最新消息被隐藏或清空时据此回退
+pub(crate) fn get_latest_visible(
+    uid: i32,
+    conversation: Conversation,
+    latest_mid: i64,
+    app_state: &AppState,
+) -> Result<Option<ChatMessage>, ServerError> {
+    let history = HistoryReq {
+        cursor: HistoryCursor::Before(Some(latest_mid + 1)),
+        limit: 1,
+    };
+    let req = match conversation {
+        Conversation::Dm(a, b) => HistoryMsgReq::User(HistoryMsgUser {
+            from_id: a as i32,
+            to_id: b as i32,
+            history,
+        }),
+        Conversation::Group(gid) => HistoryMsgReq::Group(HistoryMsgGroup {
+            gid: gid as i32,
+            history,
+        }),
+    };
+    Ok(get_history_msg(app_state, uid, req)?.messages.pop())
+}
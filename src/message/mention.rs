@@ -0,0 +1,65 @@
+use crate::app_state::AppState;
+use crate::err::ServerError;
+use crate::group::{self, GroupErr};
+use crate::message::ChatMessagePayload;
+use std::collections::BTreeSet;
+
+/// 校验群消息@的成员都在群内，@所有人需要群管理员权限，返回被@的成员（不含发送者）
+pub(super) async fn resolve_mentions(
+    payload: &ChatMessagePayload,
+    gid: i32,
+    uids: &[i32],
+    app_state: &AppState,
+) -> Result<BTreeSet<i32>, ServerError> {
+    let Some(mentions) = payload.detail.get_mentions() else {
+        return Ok(BTreeSet::new());
+    };
+    let mut mentioned = if mentions.all {
+        if !group::is_admin(gid, payload.from_uid, app_state).await? {
+            return Err(GroupErr::MentionAllNotAllowed.into());
+        }
+        uids.iter().copied().collect()
+    } else {
+        let mut mentioned = BTreeSet::new();
+        for &uid in &mentions.uids {
+            if !uids.contains(&uid) {
+                return Err(GroupErr::UserNotInGroup { uid, gid }.into());
+            }
+            mentioned.insert(uid);
+        }
+        mentioned
+    };
+    mentioned.remove(&payload.from_uid);
+    Ok(mentioned)
+}
+
+/// 群内上次已读之后是否有@该用户的消息
+pub(crate) fn has_unread_mention(
+    uid: i32,
+    gid: i32,
+    read_mid: Option<i64>,
+    app_state: &AppState,
+) -> Result<bool, ServerError> {
+    Ok(app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .has_mention_after(uid as i64, gid as i64, read_mid)?)
+}
+
+/// 查询群内上次已读之后@该用户的最多limit条消息id
+pub(crate) fn get_unread_mentions(
+    uid: i32,
+    gid: i32,
+    read_mid: Option<i64>,
+    limit: usize,
+    app_state: &AppState,
+) -> Result<Vec<i64>, ServerError> {
+    Ok(app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .fetch_mentions_after(uid as i64, gid as i64, read_mid, limit)?)
+}
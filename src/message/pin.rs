@@ -0,0 +1,123 @@
+use crate::app_state::AppState;
+use crate::auth::Token;
+use crate::datetime::datetime_format;
+use crate::err::ServerError;
+use crate::event::BroadcastEvent;
+use crate::message::{
+    build_quoted_message, get_by_mid, get_by_mids, get_participants, ChatMessage, MessageDetail,
+    MessageErr, MessageTarget, MessageTargetGroup, MessageTargetUser,
+};
+use crate::{group, Res};
+use axum::extract::{Path, State};
+use chrono::{DateTime, Local, TimeZone};
+use msg::Conversation;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// 每个会话最多置顶的消息数量
+const MAX_PINS: usize = 50;
+
+/// 置顶的消息
+#[derive(Serialize, Debug)]
+pub struct PinnedMsg {
+    /// 置顶操作人id
+    pub pinned_by: i32,
+    /// 置顶时间
+    #[serde(with = "datetime_format")]
+    pub pinned_at: DateTime<Local>,
+    pub message: ChatMessage,
+}
+
+/// 置顶消息，群聊仅群管理员可以置顶，单聊双方都可以置顶
+pub(super) async fn pin(
+    State(app_state): State<AppState>,
+    Path(mid): Path<i64>,
+    token: Token,
+) -> Res<()> {
+    set_pin(mid, true, &app_state, &token).await
+}
+
+/// 取消置顶，权限同置顶
+pub(super) async fn unpin(
+    State(app_state): State<AppState>,
+    Path(mid): Path<i64>,
+    token: Token,
+) -> Res<()> {
+    set_pin(mid, false, &app_state, &token).await
+}
+
+/// 置顶或取消置顶，置顶状态变化时通知会话中的所有用户
+async fn set_pin(mid: i64, pinned: bool, app_state: &AppState, token: &Token) -> Res<()> {
+    let message = get_by_mid(mid, app_state).ok_or(MessageErr::MessageNotExist(mid))?;
+    if pinned {
+        if let MessageDetail::Recall(_) = message.payload.detail {
+            return Err(MessageErr::MessageRecalled.into());
+        }
+    }
+    let permitted = match message.payload.target {
+        MessageTarget::User(MessageTargetUser { uid }) => {
+            token.id == uid || token.id == message.payload.from_uid
+        }
+        MessageTarget::Group(MessageTargetGroup { gid }) => {
+            group::is_admin(gid, token.id, app_state).await?
+        }
+    };
+    if !permitted {
+        return Err(MessageErr::NoPermission.into());
+    }
+    let conversation = message.payload.conversation();
+    let changed = {
+        let msg_db = app_state.msg_db.lock().unwrap();
+        let messages = msg_db.messages();
+        if pinned {
+            if messages.fetch_pins(conversation)?.len() >= MAX_PINS {
+                return Err(MessageErr::TooManyPins(MAX_PINS).into());
+            }
+            let now = Local::now().timestamp_millis();
+            messages.pin_msg(conversation, mid, token.id as i64, now)?
+        } else {
+            messages.unpin_msg(conversation, mid)?
+        }
+    };
+    if changed {
+        app_state.hub.send(BroadcastEvent::Pin {
+            targets: get_participants(&message.payload, app_state).await?,
+            mid,
+            target: message.payload.target,
+            pinned,
+            operator: token.id,
+        });
+    }
+    Ok(())
+}
+
+/// 查询会话中置顶的消息，按置顶时间倒序返回，调用方需校验用户可以查看该会话
+pub(crate) fn get_pins(
+    conversation: Conversation,
+    app_state: &AppState,
+) -> Result<Vec<PinnedMsg>, ServerError> {
+    let pins = app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .fetch_pins(conversation)?;
+    let mut mid_2_msg = get_by_mids(pins.iter().map(|pin| pin.mid).collect(), app_state)
+        .into_iter()
+        .map(|x| (x.mid, x))
+        .collect::<HashMap<i64, ChatMessage>>();
+    Ok(pins
+        .into_iter()
+        .filter_map(|pin| {
+            let message = mid_2_msg.remove(&pin.mid)?;
+            Some(PinnedMsg {
+                pinned_by: pin.pinned_by as i32,
+                pinned_at: Local
+                    .timestamp_millis_opt(pin.pinned_at)
+                    .single()
+                    .unwrap_or_default(),
+                message: build_quoted_message(message.mid, message.payload, app_state),
+            })
+        })
+        .collect())
+}
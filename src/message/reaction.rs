@@ -0,0 +1,182 @@
+use crate::app_state::AppState;
+use crate::auth::Token;
+use crate::err::ServerError;
+use crate::event::BroadcastEvent;
+use crate::message::{get_by_mid, get_participants, is_received, MessageDetail, MessageErr};
+use crate::Res;
+use axum::extract::{Path, State};
+use serde::Serialize;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+/// 单条消息最多的表情回应种类
+const MAX_REACTION_KINDS: usize = 20;
+
+/// 表情回应的最大字节数，组合emoji由多个码点组成
+const MAX_EMOJI_LEN: usize = 32;
+
+/// 消息的表情回应汇总
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub struct ReactionSummary {
+    /// 表情
+    pub emoji: String,
+    /// 回应人数
+    pub count: usize,
+    /// 当前用户是否回应了该表情
+    pub reacted: bool,
+}
+
+/// 对消息添加表情回应
+pub(super) async fn add_reaction(
+    State(app_state): State<AppState>,
+    Path((mid, emoji)): Path<(i64, String)>,
+    token: Token,
+) -> Res<()> {
+    react(mid, emoji, true, &app_state, &token).await
+}
+
+/// 取消对消息的表情回应
+pub(super) async fn remove_reaction(
+    State(app_state): State<AppState>,
+    Path((mid, emoji)): Path<(i64, String)>,
+    token: Token,
+) -> Res<()> {
+    react(mid, emoji, false, &app_state, &token).await
+}
+
+/// 可以单独作为表情的码点，不含国旗与肤色等只能组合使用的码点
+fn is_emoji_base(c: char) -> bool {
+    matches!(c,
+        '\u{a9}' | '\u{ae}' | '\u{203c}' | '\u{2049}' | '\u{2122}' | '\u{2139}'
+        | '\u{2194}'..='\u{21ff}'
+        | '\u{2300}'..='\u{23ff}'
+        | '\u{24c2}'
+        | '\u{25aa}'..='\u{25fe}'
+        | '\u{2600}'..='\u{27bf}'
+        | '\u{2934}' | '\u{2935}'
+        | '\u{2b00}'..='\u{2bff}'
+        | '\u{3030}' | '\u{303d}' | '\u{3297}' | '\u{3299}'
+        | '\u{1f000}'..='\u{1f1e5}'
+        | '\u{1f200}'..='\u{1f3fa}'
+        | '\u{1f400}'..='\u{1faff}')
+}
+
+/// 是否为单个表情：国旗、键帽、旗帜标签序列，或者由零宽连接符连接的带可选变体选择符与肤色的表情
+fn is_emoji(emoji: &str) -> bool {
+    let chars = emoji.chars().collect::<Vec<char>>();
+    let regional = |c: &char| ('\u{1f1e6}'..='\u{1f1ff}').contains(c);
+    match chars.as_slice() {
+        [] => false,
+        [a, b] if regional(a) => regional(b),
+        [key, rest @ ..] if key.is_ascii_digit() || *key == '#' || *key == '*' => {
+            matches!(rest, ['\u{20e3}'] | ['\u{fe0f}', '\u{20e3}'])
+        }
+        ['\u{1f3f4}', tags @ .., '\u{e007f}'] if !tags.is_empty() => {
+            tags.iter().all(|c| ('\u{e0020}'..='\u{e007e}').contains(c))
+        }
+        _ => chars.split(|&c| c == '\u{200d}').all(|part| match part {
+            [base, rest @ ..] if is_emoji_base(*base) => matches!(
+                rest,
+                [] | ['\u{fe0f}']
+                    | ['\u{1f3fb}'..='\u{1f3ff}']
+                    | ['\u{1f3fb}'..='\u{1f3ff}', '\u{fe0f}']
+            ),
+            _ => false,
+        }),
+    }
+}
+
+/// 添加或取消表情回应，回应有变化时通知会话中的所有用户
+async fn react(
+    mid: i64,
+    emoji: String,
+    added: bool,
+    app_state: &AppState,
+    token: &Token,
+) -> Res<()> {
+    if emoji.len() > MAX_EMOJI_LEN || !is_emoji(&emoji) {
+        return Err(MessageErr::InvalidReaction.into());
+    }
+    let message = get_by_mid(mid, app_state).ok_or(MessageErr::MessageNotExist(mid))?;
+    if let MessageDetail::Recall(_) = message.payload.detail {
+        return Err(MessageErr::MessageRecalled.into());
+    }
+    // 已退出群聊的用户不能再回应
+    let targets = get_participants(&message.payload, app_state).await?;
+    if !targets.contains(&token.id) || !is_received(token.id, mid, app_state)? {
+        return Err(MessageErr::NoPermission.into());
+    }
+    let count = {
+        let msg_db = app_state.msg_db.lock().unwrap();
+        let messages = msg_db.messages();
+        let changed = if added {
+            let reactions = messages.fetch_reactions(mid)?;
+            if reactions.len() >= MAX_REACTION_KINDS && reactions.iter().all(|(e, _)| *e != emoji) {
+                return Err(MessageErr::TooManyReactions(MAX_REACTION_KINDS).into());
+            }
+            messages.add_reaction(mid, token.id as i64, &emoji)?
+        } else {
+            messages.remove_reaction(mid, token.id as i64, &emoji)?
+        };
+        if !changed {
+            return Ok(());
+        }
+        messages
+            .fetch_reactions(mid)?
+            .into_iter()
+            .find(|(e, _)| *e == emoji)
+            .map_or(0, |(_, uids)| uids.len())
+    };
+    app_state.hub.send(BroadcastEvent::Reaction {
+        targets,
+        mid,
+        target: message.payload.target,
+        uid: token.id,
+        emoji,
+        added,
+        count,
+    });
+    Ok(())
+}
+
+/// 查询消息的表情回应汇总，uid为当前用户
+pub(crate) fn get_reactions(
+    mids: impl IntoIterator<Item = i64>,
+    uid: i32,
+    app_state: &AppState,
+) -> Result<HashMap<i64, Vec<ReactionSummary>>, ServerError> {
+    let msg_db = app_state.msg_db.lock().unwrap();
+    let mut result = HashMap::new();
+    for mid in mids {
+        let reactions = msg_db.messages().fetch_reactions(mid)?;
+        if reactions.is_empty() {
+            continue;
+        }
+        let summaries = reactions
+            .into_iter()
+            .map(|(emoji, uids)| ReactionSummary {
+                emoji,
+                count: uids.len(),
+                reacted: uids.contains(&(uid as i64)),
+            })
+            .collect();
+        result.insert(mid, summaries);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::message::reaction::is_emoji;
+
+    #[test]
+    fn single_emoji_only() {
+        let emojis = ["👍", "❤️", "👍🏽", "🇨🇳", "1️⃣", "👨‍👩‍👧", "🏴󠁧󠁢󠁳󠁣󠁴󠁿", "🧑🏻‍💻"];
+        for emoji in emojis {
+            assert!(is_emoji(emoji), "{emoji}");
+        }
+        for text in ["", "ok", "👍👍", "👍 ", "🇨", "1", "<b>", "a\u{200d}b", "🏽"] {
+            assert!(!is_emoji(text), "{text}");
+        }
+    }
+}
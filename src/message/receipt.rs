@@ -0,0 +1,137 @@
+use crate::app_state::AppState;
+use crate::auth::Token;
+use crate::datetime::opt_datetime_format;
+use crate::err::ServerError;
+use crate::event::BroadcastEvent;
+use crate::message::{
+    build_chat_message, get_by_mid, ChatMessage, MessageErr, MessageTarget, MessageTargetUser,
+};
+use crate::Res;
+use axum::extract::{Path, State};
+use axum::Json;
+use chrono::{DateTime, Local, TimeZone};
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+/// 回执状态
+#[derive(Serialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReceiptStatus {
+    /// 已送达接收者的事件流
+    Delivered,
+    /// 接收者已读
+    Read,
+}
+
+/// 单聊消息的回执
+#[derive(Serialize, Debug)]
+pub struct ReceiptVo {
+    /// 送达时间，未送达时为空
+    #[serde(with = "opt_datetime_format")]
+    pub delivered_at: Option<DateTime<Local>>,
+    /// 已读时间，未读时为空
+    #[serde(with = "opt_datetime_format")]
+    pub read_at: Option<DateTime<Local>>,
+}
+
+/// 每批检查已读回执的消息数量
+const RECEIPT_BATCH: usize = 100;
+
+/// 查询单聊消息的回执，仅单聊双方可以查询
+pub(super) async fn receipt(
+    State(app_state): State<AppState>,
+    Path(mid): Path<i64>,
+    token: Token,
+) -> Res<Json<ReceiptVo>> {
+    let message = get_by_mid(mid, &app_state).ok_or(MessageErr::MessageNotExist(mid))?;
+    let MessageTarget::User(MessageTargetUser { uid }) = message.payload.target else {
+        return Err(MessageErr::NotDirectMessage(mid).into());
+    };
+    if token.id != uid && token.id != message.payload.from_uid {
+        return Err(MessageErr::NoPermission.into());
+    }
+    let receipt = app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .get_receipt(mid)?;
+    let to_datetime = |at: i64| Local.timestamp_millis_opt(at).single().unwrap_or_default();
+    Ok(Json(ReceiptVo {
+        delivered_at: receipt.delivered_at.map(to_datetime),
+        read_at: receipt.read_at.map(to_datetime),
+    }))
+}
+
+/// 单聊消息推送给接收者后记录送达回执，首次送达时通知发送者
+pub(crate) fn deliver(
+    uid: i32,
+    message: &ChatMessage,
+    app_state: &AppState,
+) -> Result<(), ServerError> {
+    let MessageTarget::User(MessageTargetUser { uid: to_uid }) = message.payload.target else {
+        return Ok(());
+    };
+    if to_uid != uid || message.payload.from_uid == uid {
+        return Ok(());
+    }
+    let now = Local::now();
+    let delivered = app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .mark_delivered(message.mid, now.timestamp_millis())?;
+    if delivered {
+        app_state.hub.send(BroadcastEvent::Receipt {
+            targets: BTreeSet::from([message.payload.from_uid]),
+            uid,
+            mids: vec![message.mid],
+            status: ReceiptStatus::Delivered,
+            at: now,
+        });
+    }
+    Ok(())
+}
+
+/// 单聊的已读位置从after前进到read_mid后，记录对方发送的消息的已读回执并通知对方
+pub(crate) fn read_dm(
+    uid: i32,
+    target_uid: i32,
+    after: Option<i64>,
+    read_mid: i64,
+    app_state: &AppState,
+) -> Result<(), ServerError> {
+    let now = Local::now();
+    let mut after = after.unwrap_or_default();
+    let mut read = Vec::new();
+    while after < read_mid {
+        let msg_db = app_state.msg_db.lock().unwrap();
+        let msgs = msg_db.messages().fetch_dm_messages_after(
+            uid as i64,
+            target_uid as i64,
+            after,
+            RECEIPT_BATCH,
+        )?;
+        let Some((last, _)) = msgs.last() else {
+            break;
+        };
+        after = *last;
+        let mids = msgs
+            .into_iter()
+            .filter(|(mid, _)| *mid <= read_mid)
+            .filter_map(|(mid, msg)| build_chat_message(mid, msg))
+            .filter(|message| message.payload.from_uid == target_uid)
+            .map(|message| message.mid);
+        read.extend(msg_db.messages().mark_read(mids, now.timestamp_millis())?);
+    }
+    if !read.is_empty() {
+        app_state.hub.send(BroadcastEvent::Receipt {
+            targets: BTreeSet::from([target_uid]),
+            uid,
+            mids: read,
+            status: ReceiptStatus::Read,
+            at: now,
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,209 @@
+use crate::app_state::AppState;
+use crate::auth::Token;
+use crate::datetime::datetime_format;
+use crate::err::ServerError;
+use crate::friend::FriendErr;
+use crate::group::GroupErr;
+use crate::message::{get_by_mids, HistoryPage, MessageTarget};
+use crate::{friend, group, Res};
+use axum::extract::{Query, State};
+use axum::Json;
+use chrono::{DateTime, Local};
+use msg::Conversation;
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+/// 更新消息的搜索索引，old_text为原先建立索引的文本
+pub(super) fn update_index(
+    mid: i64,
+    conversation: Conversation,
+    old_text: Option<String>,
+    new_text: Option<String>,
+    app_state: &AppState,
+) -> Result<(), ServerError> {
+    let msg_db = app_state.msg_db.lock().unwrap();
+    if let Some(text) = old_text {
+        msg_db.messages().unindex_msg(conversation, mid, &text)?;
+    }
+    if let Some(text) = new_text {
+        msg_db.messages().index_msg(conversation, mid, &text)?;
+    }
+    Ok(())
+}
+
+/// 搜索结果默认每页数量
+const DEFAULT_SEARCH_LIMIT: usize = 20;
+
+/// 搜索结果片段中命中词前后保留的字符数
+const SNIPPET_CONTEXT: usize = 20;
+
+/// 消息搜索参数，uid、gid最多指定一个，都不指定时搜索用户的所有会话
+#[derive(Deserialize, Validate, Debug)]
+#[validate(schema(function = "validate_search_query"))]
+pub struct SearchQuery {
+    /// 搜索词，多个词以空格分隔，消息需包含所有词
+    #[validate(length(min = 1, max = 100))]
+    pub q: String,
+    /// 在与该好友的单聊中搜索
+    pub uid: Option<i32>,
+    /// 在该群中搜索
+    pub gid: Option<i32>,
+    /// 搜索该消息之前的消息
+    pub before: Option<i64>,
+    /// 每页数量，默认20，最大100
+    #[validate(range(min = 1, max = 100))]
+    pub limit: Option<usize>,
+}
+
+fn validate_search_query(query: &SearchQuery) -> Result<(), ValidationError> {
+    if query.uid.is_some() && query.gid.is_some() {
+        return Err(
+            ValidationError::new("1").with_message("only one of uid and gid is allowed".into())
+        );
+    }
+    Ok(())
+}
+
+/// 搜索命中的消息
+#[derive(Serialize, Debug)]
+pub struct SearchHit {
+    pub mid: i64,
+    pub from_uid: i32,
+    #[serde(with = "datetime_format")]
+    pub time: DateTime<Local>,
+    pub target: MessageTarget,
+    /// 命中处前后的文本片段
+    pub snippet: String,
+    /// 片段中命中词的字符区间，左闭右开
+    pub highlights: Vec<[usize; 2]>,
+}
+
+/// 搜索消息，按消息id降序返回，只返回用户当前可以看到的消息
+pub(super) async fn search(
+    State(app_state): State<AppState>,
+    token: Token,
+    Query(query): Query<SearchQuery>,
+) -> Res<Json<HistoryPage<SearchHit>>> {
+    query.validate()?;
+    let conversations = match (query.uid, query.gid) {
+        (Some(uid), _) => {
+            if !friend::is_friend(token.dgraph_uid.clone(), uid).await {
+                return Err(FriendErr::NotFriend(uid).into());
+            }
+            vec![Conversation::dm(token.id as i64, uid as i64)]
+        }
+        (_, Some(gid)) => {
+            if !group::in_group(gid, token.id, &app_state).await? {
+                return Err(GroupErr::UserNotInGroup { uid: token.id, gid }.into());
+            }
+            vec![Conversation::Group(gid as i64)]
+        }
+        // 退出的群、删除的好友不再可以搜索
+        _ => friend::get_friend_ids(&token.dgraph_uid)
+            .await?
+            .into_iter()
+            .map(|uid| Conversation::dm(token.id as i64, uid as i64))
+            .chain(
+                group::get_memberships(token.id, &app_state)
+                    .await?
+                    .into_iter()
+                    .map(|membership| Conversation::Group(membership.gid as i64)),
+            )
+            .collect(),
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+    let mut found = vec![];
+    // 逐个会话搜索后合并，每个会话搜索完即释放锁
+    for conversation in conversations {
+        found.extend(app_state.msg_db.lock().unwrap().messages().search(
+            conversation,
+            &query.q,
+            query.before,
+            limit + 1,
+        )?);
+    }
+    found.sort_unstable_by(|a, b| b.cmp(a));
+    let has_more = found.len() > limit;
+    found.truncate(limit);
+    let next_cursor = found.last().filter(|_| has_more).copied();
+    let messages = get_by_mids(found, &app_state)
+        .into_iter()
+        .map(|message| {
+            let text = message
+                .payload
+                .detail
+                .get_searchable_text()
+                .unwrap_or_default();
+            let (snippet, highlights) = snippet(&text, &query.q);
+            SearchHit {
+                mid: message.mid,
+                from_uid: message.payload.from_uid,
+                time: message.payload.created_at,
+                target: message.payload.target,
+                snippet,
+                highlights,
+            }
+        })
+        .collect();
+    Ok(Json(HistoryPage {
+        messages,
+        next_cursor,
+        has_more,
+        has_newer: false,
+    }))
+}
+
+/// 截取文本中第一个命中处前后的片段，返回片段及片段中所有命中词的字符区间
+fn snippet(text: &str, query: &str) -> (String, Vec<[usize; 2]>) {
+    let lowercase = |c: char| c.to_lowercase().next().unwrap_or(c);
+    let chars = text.chars().collect::<Vec<char>>();
+    let lower = chars.iter().map(|&c| lowercase(c)).collect::<Vec<char>>();
+    let mut terms = query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(|term| term.chars().map(lowercase).collect::<Vec<char>>())
+        .collect::<Vec<Vec<char>>>();
+    // 优先匹配更长的词
+    terms.sort_by_key(|term| std::cmp::Reverse(term.len()));
+    let mut hits = vec![];
+    let mut i = 0;
+    while i < lower.len() {
+        match terms.iter().find(|term| lower[i..].starts_with(term)) {
+            Some(term) => {
+                hits.push([i, i + term.len()]);
+                i += term.len();
+            }
+            None => i += 1,
+        }
+    }
+    let (start, end) = match hits.first() {
+        Some(&[from, to]) => (
+            from.saturating_sub(SNIPPET_CONTEXT),
+            (to + SNIPPET_CONTEXT).min(chars.len()),
+        ),
+        None => (0, (SNIPPET_CONTEXT * 2).min(chars.len())),
+    };
+    let highlights = hits
+        .into_iter()
+        .filter(|&[from, to]| from >= start && to <= end)
+        .map(|[from, to]| [from - start, to - start])
+        .collect();
+    (chars[start..end].iter().collect(), highlights)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::message::search::snippet;
+
+    #[test]
+    fn snippet_highlights() {
+        let (text, highlights) = snippet("周末一起去爬山吗？Rust大会也在周末", "周末 rust");
+        assert_eq!(text, "周末一起去爬山吗？Rust大会也在周末");
+        assert_eq!(highlights, vec![[0, 2], [9, 13], [17, 19]]);
+
+        let long = format!("{}关键词{}", "前".repeat(30), "后".repeat(30));
+        let (text, highlights) = snippet(&long, "关键词");
+        assert_eq!(text.chars().count(), 43);
+        assert_eq!(highlights, vec![[20, 23]]);
+    }
+}
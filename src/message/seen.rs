@@ -0,0 +1,111 @@
+use crate::app_state::AppState;
+use crate::auth::Token;
+use crate::err::ServerError;
+use crate::event::BroadcastEvent;
+use crate::message::{
+    build_chat_message, get_by_mid, ChatMessage, MessageErr, MessageTarget, MessageTargetGroup,
+};
+use crate::{group, read_index, Res};
+use axum::extract::{Path, State};
+use axum::Json;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+
+/// 群消息的已读与未读成员，不含发送者
+#[derive(Serialize, Debug)]
+pub struct SeenByVo {
+    pub read_by: Vec<i32>,
+    pub unread_by: Vec<i32>,
+}
+
+/// 群消息的已读人数
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct SeenCount {
+    pub mid: i64,
+    pub count: usize,
+}
+
+/// 群成员已读位置前进时，最多推送已读人数变化的消息数量（从已读位置向前）
+const SEEN_BATCH: usize = 100;
+
+/// 查询群消息的已读与未读成员，仅发送者与群管理员可以查询
+pub(super) async fn seen_by(
+    State(app_state): State<AppState>,
+    Path(mid): Path<i64>,
+    token: Token,
+) -> Res<Json<SeenByVo>> {
+    let message = get_by_mid(mid, &app_state).ok_or(MessageErr::MessageNotExist(mid))?;
+    let MessageTarget::Group(MessageTargetGroup { gid }) = message.payload.target else {
+        return Err(MessageErr::NotGroupMessage(mid).into());
+    };
+    let from_uid = message.payload.from_uid;
+    if token.id != from_uid && !group::is_admin(gid, token.id, &app_state).await? {
+        return Err(MessageErr::NoPermission.into());
+    }
+    let read_mids = read_index::get_group_read_mids(gid, &app_state).await?;
+    let (read_by, unread_by) = group::get_uids(&app_state, gid)
+        .await?
+        .into_iter()
+        .filter(|&uid| uid != from_uid)
+        .partition(|uid| read_mids.get(uid).is_some_and(|&read| read >= mid));
+    Ok(Json(SeenByVo { read_by, unread_by }))
+}
+
+/// 群成员的已读位置从after前进到read_mid后，将新读到的消息的已读人数推送给各消息的发送者
+pub(crate) async fn read_group(
+    uid: i32,
+    gid: i32,
+    after: Option<i64>,
+    read_mid: i64,
+    app_state: &AppState,
+) -> Result<(), ServerError> {
+    // 已读位置没有前进，或者已不在群中时不推送
+    if after.is_some_and(|after| read_mid <= after) {
+        return Ok(());
+    }
+    let uids = group::get_uids(app_state, gid).await?;
+    if !uids.contains(&uid) {
+        return Ok(());
+    }
+    let msgs = app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .fetch_group_messages_before(gid as i64, Some(read_mid.saturating_add(1)), SEEN_BATCH)?;
+    let msgs = msgs
+        .into_iter()
+        .filter(|(mid, _)| Some(*mid) > after)
+        .filter_map(|(mid, msg)| build_chat_message(mid, msg))
+        .filter(|message| message.payload.from_uid != uid)
+        .collect::<Vec<ChatMessage>>();
+    if msgs.is_empty() {
+        return Ok(());
+    }
+    let read_mids = read_index::get_group_read_mids(gid, app_state).await?;
+    let mut sender_2_seen: HashMap<i32, Vec<SeenCount>> = HashMap::new();
+    for message in msgs {
+        let from_uid = message.payload.from_uid;
+        let count = uids
+            .iter()
+            .filter(|&&member| member != from_uid)
+            .filter(|member| {
+                read_mids
+                    .get(member)
+                    .is_some_and(|&read| read >= message.mid)
+            })
+            .count();
+        sender_2_seen.entry(from_uid).or_default().push(SeenCount {
+            mid: message.mid,
+            count,
+        });
+    }
+    for (sender, seen) in sender_2_seen {
+        app_state.hub.send(BroadcastEvent::Seen {
+            targets: BTreeSet::from([sender]),
+            gid,
+            seen,
+        });
+    }
+    Ok(())
+}
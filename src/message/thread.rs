@@ -0,0 +1,148 @@
+use crate::app_state::AppState;
+use crate::auth::Token;
+use crate::datetime::datetime_format;
+use crate::err::ServerError;
+use crate::group::GroupErr;
+use crate::message::{
+    get_by_mid, get_history_msg, ChatMessage, HistoryMsgReq, HistoryMsgThread, HistoryPage,
+    HistoryQuery, MessageDetail, MessageErr, MessageTarget, MessageTargetGroup, SendMsgReq,
+};
+use crate::validate::ValidatedJson;
+use crate::{group, Res};
+use axum::extract::{Path, Query, State};
+use axum::Json;
+use chrono::{DateTime, Local, TimeZone};
+use msg::ThreadStat;
+use serde::Serialize;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+use validator::Validate;
+
+/// 话题的回复统计
+#[derive(Serialize, Clone, Copy, Debug, ToSchema)]
+pub struct ThreadSummary {
+    /// 回复数量
+    pub count: i64,
+    /// 最后一条回复的消息id
+    pub last_reply: i64,
+    /// 最后一条回复的时间
+    #[serde(with = "datetime_format")]
+    pub last_reply_at: DateTime<Local>,
+}
+
+impl From<ThreadStat> for ThreadSummary {
+    fn from(stat: ThreadStat) -> Self {
+        ThreadSummary {
+            count: stat.count,
+            last_reply: stat.last_reply,
+            last_reply_at: Local
+                .timestamp_millis_opt(stat.last_reply_at)
+                .single()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// 校验消息可以作为话题的根消息（群消息且本身不是话题回复），返回根消息及所在的群
+fn get_thread_root(root: i64, app_state: &AppState) -> Result<(ChatMessage, i32), ServerError> {
+    let message = get_by_mid(root, app_state).ok_or(MessageErr::MessageNotExist(root))?;
+    match (message.payload.target, message.payload.thread) {
+        (MessageTarget::Group(MessageTargetGroup { gid }), None) => Ok((message, gid)),
+        _ => Err(MessageErr::NotThreadable(root).into()),
+    }
+}
+
+/// 分页查询话题的回复，参数同会话的历史消息
+pub(super) async fn thread(
+    State(app_state): State<AppState>,
+    Path(root): Path<i64>,
+    token: Token,
+    Query(query): Query<HistoryQuery>,
+) -> Res<Json<HistoryPage<ChatMessage>>> {
+    query.validate()?;
+    let (_, gid) = get_thread_root(root, &app_state)?;
+    if !group::in_group(gid, token.id, &app_state).await? {
+        return Err(GroupErr::UserNotInGroup { uid: token.id, gid }.into());
+    }
+    let page = get_history_msg(
+        &app_state,
+        token.id,
+        HistoryMsgReq::Thread(HistoryMsgThread {
+            root,
+            history: query.into(),
+        }),
+    )?;
+    Ok(Json(page))
+}
+
+/// 在群消息下回复，该消息成为话题的根消息
+pub(super) async fn reply_thread(
+    State(app_state): State<AppState>,
+    Path(root): Path<i64>,
+    token: Token,
+    ValidatedJson(req): ValidatedJson<SendMsgReq>,
+) -> Res<String> {
+    let (message, gid) = get_thread_root(root, &app_state)?;
+    if let MessageDetail::Recall(_) = message.payload.detail {
+        return Err(MessageErr::MessageRecalled.into());
+    }
+    let mid = group::send_to_thread(&app_state, &token, gid, &message, req.into_detail()).await?;
+    Ok(mid.to_string())
+}
+
+/// 订阅话题，订阅后通过事件流接收话题的新回复
+pub(super) async fn subscribe_thread(
+    State(app_state): State<AppState>,
+    Path(root): Path<i64>,
+    token: Token,
+) -> Res<()> {
+    let (_, gid) = get_thread_root(root, &app_state)?;
+    if !group::in_group(gid, token.id, &app_state).await? {
+        return Err(GroupErr::UserNotInGroup { uid: token.id, gid }.into());
+    }
+    set_thread_subscriptions(root, [token.id], &app_state)?;
+    Ok(())
+}
+
+/// 取消订阅话题，之后只接收话题统计的变化
+pub(super) async fn unsubscribe_thread(
+    State(app_state): State<AppState>,
+    Path(root): Path<i64>,
+    token: Token,
+) -> Res<()> {
+    app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .unsubscribe_thread(root, token.id as i64)?;
+    Ok(())
+}
+
+/// 为用户订阅话题
+pub(crate) fn set_thread_subscriptions(
+    root: i64,
+    uids: impl IntoIterator<Item = i32>,
+    app_state: &AppState,
+) -> Result<(), ServerError> {
+    let msg_db = app_state.msg_db.lock().unwrap();
+    for uid in uids {
+        msg_db.messages().subscribe_thread(root, uid as i64)?;
+    }
+    Ok(())
+}
+
+/// 查询消息作为话题根消息的回复统计，没有回复的消息不返回
+pub(crate) fn get_threads(
+    mids: impl IntoIterator<Item = i64>,
+    app_state: &AppState,
+) -> Result<HashMap<i64, ThreadSummary>, ServerError> {
+    let msg_db = app_state.msg_db.lock().unwrap();
+    let mut result = HashMap::new();
+    for mid in mids {
+        if let Some(stat) = msg_db.messages().get_thread_stat(mid)? {
+            result.insert(mid, stat.into());
+        }
+    }
+    Ok(result)
+}
@@ -1,7 +1,7 @@
 use crate::app_state::AppState;
 use crate::auth::{AuthError, Token};
 use crate::err::ServerError;
-use crate::{auth, user};
+use crate::{auth, presence, user};
 use axum::extract::{Request, State};
 use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
@@ -21,10 +21,17 @@ pub(crate) async fn check_user_status(
 }
 
 // 校验token有效期
-pub(crate) async fn check_login(token: Token, request: Request, next: Next) -> Response {
+pub(crate) async fn check_login(
+    State(state): State<AppState>,
+    token: Token,
+    request: Request,
+    next: Next,
+) -> Response {
+    let uid = token.id;
     if let Err(err) = auth::check_token_expire(token).await {
         return ServerError::from(err).into_response();
     }
+    presence::active(uid, &state);
     let response = next.run(request).await;
     response
 }
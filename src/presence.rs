@@ -0,0 +1,229 @@
+use std::collections::{BTreeSet, HashMap};
+use std::mem;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use entity::prelude::User;
+use entity::user;
+use sea_orm::sea_query::Expr;
+use sea_orm::{ColumnTrait, DbErr, EntityTrait, QueryFilter};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::app_state::AppState;
+use crate::datetime::{self, opt_datetime_format};
+use crate::err::ServerError;
+use crate::event::BroadcastEvent;
+use crate::friend;
+use crate::user::UserErr;
+
+/// 无活动超过该时长的在线用户视为空闲
+const IDLE_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// 检查空闲用户的间隔
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 持有事件流连接的用户最近一次活动的情况，不在其中的用户视为离线
+#[derive(Default)]
+pub struct Activities {
+    users: Mutex<HashMap<i32, Activity>>,
+}
+
+struct Activity {
+    active_at: Instant,
+    idle: bool,
+}
+
+impl Activities {
+    /// 记录用户的活动，返回用户是否由离线或空闲变为在线。connected为false时只更新已在线的用户
+    fn touch(&self, uid: i32, connected: bool) -> bool {
+        let mut activities = self.users.lock().unwrap();
+        if connected {
+            activities.entry(uid).or_insert(Activity {
+                active_at: Instant::now(),
+                idle: true,
+            });
+        }
+        let Some(activity) = activities.get_mut(&uid) else {
+            return false;
+        };
+        activity.active_at = Instant::now();
+        mem::replace(&mut activity.idle, false)
+    }
+
+    /// 没有事件流连接的用户为离线
+    fn presence(&self, uid: i32) -> Presence {
+        match self.users.lock().unwrap().get(&uid) {
+            None => Presence::Offline,
+            Some(activity) if activity.idle => Presence::Idle,
+            Some(_) => Presence::Online,
+        }
+    }
+
+    /// 将长时间无活动的在线用户标记为空闲，返回新变为空闲的用户
+    fn mark_idle(&self) -> Vec<i32> {
+        self.users
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .filter(|(_, activity)| !activity.idle && activity.active_at.elapsed() >= IDLE_AFTER)
+            .map(|(&uid, activity)| {
+                activity.idle = true;
+                uid
+            })
+            .collect()
+    }
+}
+
+/// 用户在线状态
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Presence {
+    Online,
+    Idle,
+    #[default]
+    Offline,
+}
+
+/// 好友看到的在线状态，离线时附带最后在线时间，用户隐藏最后在线时间时为空
+#[derive(Serialize, Clone, Copy, Debug, Default)]
+pub struct PresenceVo {
+    pub presence: Presence,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "opt_datetime_format"
+    )]
+    pub last_seen: Option<DateTime<Local>>,
+}
+
+/// 查询用户的在线状态
+pub(crate) fn get_presence(user: &user::Model, app_state: &AppState) -> PresenceVo {
+    let presence = app_state.activities.presence(user.id);
+    PresenceVo {
+        presence,
+        last_seen: last_seen(presence, user),
+    }
+}
+
+fn last_seen(presence: Presence, user: &user::Model) -> Option<DateTime<Local>> {
+    if presence != Presence::Offline || user.hide_last_seen {
+        return None;
+    }
+    user.last_seen.map(datetime::native_datetime_2_datetime)
+}
+
+/// 用户建立事件流连接后调用
+pub(crate) async fn connected(uid: i32, app_state: &AppState) {
+    if app_state.activities.touch(uid, true) {
+        notify(uid, Presence::Online, app_state).await;
+    }
+}
+
+/// 用户有请求或上行指令时调用，空闲的用户恢复为在线
+pub(crate) fn active(uid: i32, app_state: &AppState) {
+    if app_state.activities.touch(uid, false) {
+        let app_state = app_state.clone();
+        tokio::spawn(async move { notify(uid, Presence::Online, &app_state).await });
+    }
+}
+
+/// 用户的事件流连接断开后调用，没有其他连接时记录最后在线时间并通知好友下线
+pub(crate) async fn disconnected(uid: i32, app_state: &AppState) {
+    {
+        let mut activities = app_state.activities.users.lock().unwrap();
+        if app_state.hub.connections_of(uid) > 0 || activities.remove(&uid).is_none() {
+            return;
+        }
+    }
+    if let Err(err) = set_last_seen(uid, Local::now(), app_state).await {
+        warn!("fail to set last seen of user {uid}: {err}");
+    }
+    notify(uid, Presence::Offline, app_state).await;
+}
+
+async fn set_last_seen(
+    uid: i32,
+    last_seen: DateTime<Local>,
+    app_state: &AppState,
+) -> Result<(), DbErr> {
+    User::update_many()
+        .col_expr(user::Column::LastSeen, Expr::value(last_seen.naive_utc()))
+        .filter(user::Column::Id.eq(uid))
+        .exec(&app_state.db)
+        .await?;
+    Ok(())
+}
+
+/// 设置是否对好友隐藏最后在线时间
+pub(crate) async fn hide_last_seen(
+    uid: i32,
+    hidden: bool,
+    app_state: &AppState,
+) -> Result<(), DbErr> {
+    User::update_many()
+        .col_expr(user::Column::HideLastSeen, Expr::value(hidden))
+        .filter(user::Column::Id.eq(uid))
+        .exec(&app_state.db)
+        .await?;
+    Ok(())
+}
+
+/// 后台将长时间无活动的在线用户标记为空闲并通知好友
+pub async fn sweep_idle(app_state: AppState) {
+    let mut interval = tokio::time::interval(IDLE_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let idle = app_state.activities.mark_idle();
+        for uid in idle {
+            notify(uid, Presence::Idle, &app_state).await;
+        }
+    }
+}
+
+/// 将用户的在线状态变化推送给好友
+async fn notify(uid: i32, presence: Presence, app_state: &AppState) {
+    if let Err(err) = try_notify(uid, presence, app_state).await {
+        warn!("fail to notify presence of user {uid}: {err}");
+    }
+}
+
+async fn try_notify(uid: i32, presence: Presence, app_state: &AppState) -> Result<(), ServerError> {
+    let user = crate::user::get_by_id(uid, app_state)
+        .await?
+        .ok_or(UserErr::UserNotExist(uid))?;
+    let targets = friend::get_friend_ids(&user.dgraph_uid)
+        .await?
+        .into_iter()
+        .collect::<BTreeSet<i32>>();
+    if targets.is_empty() {
+        return Ok(());
+    }
+    app_state.hub.send(BroadcastEvent::Presence {
+        targets,
+        uid,
+        presence: PresenceVo {
+            presence,
+            last_seen: last_seen(presence, &user),
+        },
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::presence::{Activities, Presence};
+
+    #[test]
+    fn touch_only_wakes_connected_users() {
+        let activities = Activities::default();
+        assert!(!activities.touch(1, false));
+        assert!(activities.touch(1, true));
+        assert!(!activities.touch(1, true));
+        activities.users.lock().unwrap().get_mut(&1).unwrap().idle = true;
+        assert_eq!(activities.presence(1), Presence::Idle);
+        assert!(activities.touch(1, false));
+        assert_eq!(activities.presence(1), Presence::Online);
+        assert!(!activities.touch(2, false));
+    }
+}
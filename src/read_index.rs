@@ -1,15 +1,19 @@
 use crate::app_state::AppState;
 use crate::auth::Token;
 use crate::err::ServerError;
+use crate::message::{MessageTarget, MessageTargetGroup, MessageTargetUser};
 use crate::{group, message, middleware, Api, Res};
 use axum::extract::State;
 use axum::routing::put;
 use axum::{Json, Router};
 use entity::read_index;
 use entity::read_index::{ActiveModel, Model};
+use msg::Conversation;
+use sea_orm::sea_query::Expr;
 use sea_orm::ActiveValue::Set;
-use sea_orm::{sea_query, DbErr, EntityTrait, NotSet};
+use sea_orm::{sea_query, ColumnTrait, Condition, DbErr, EntityTrait, NotSet, QueryFilter};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 pub struct ReadIndexApi;
 
@@ -45,8 +49,15 @@ pub(crate) async fn set_read_index(
     uid: i32,
     read_index: UpdateReadIndex,
 ) -> Result<(), ServerError> {
+    let version = next_version(app_state)?;
     Ok(match read_index {
         UpdateReadIndex::User { target_uid, mid } => {
+            let read_mid = read_index::Entity::find()
+                .filter(read_index::Column::Uid.eq(uid))
+                .filter(read_index::Column::TargetUid.eq(target_uid))
+                .one(&app_state.db)
+                .await?
+                .and_then(|ri| ri.mid);
             let active_model = ActiveModel {
                 id: Default::default(),
                 uid: Set(uid),
@@ -55,6 +66,7 @@ pub(crate) async fn set_read_index(
                 mid: Set(Some(mid)),
                 latest_mid: Set(mid),
                 uid_of_latest_msg: Set(uid),
+                version: Set(version),
             };
             let result = read_index::Entity::insert(active_model)
                 .on_conflict(
@@ -66,6 +78,7 @@ pub(crate) async fn set_read_index(
                         read_index::Column::Mid,
                         read_index::Column::LatestMid,
                         read_index::Column::UidOfLatestMsg,
+                        read_index::Column::Version,
                     ])
                     .to_owned(),
                 )
@@ -82,6 +95,7 @@ pub(crate) async fn set_read_index(
                 mid: Set(None),
                 latest_mid: Set(mid),
                 uid_of_latest_msg: Set(uid),
+                version: Set(version),
             };
             read_index::Entity::insert(active_model)
                 .on_conflict(
@@ -92,13 +106,21 @@ pub(crate) async fn set_read_index(
                     .update_columns(vec![
                         read_index::Column::LatestMid,
                         read_index::Column::UidOfLatestMsg,
+                        read_index::Column::Version,
                     ])
                     .to_owned(),
                 )
                 .exec(&app_state.db)
                 .await?;
+            message::read_dm(uid, target_uid, read_mid, mid, app_state)?;
         }
         UpdateReadIndex::Group { target_gid, mid } => {
+            let read_mid = read_index::Entity::find()
+                .filter(read_index::Column::Uid.eq(uid))
+                .filter(read_index::Column::TargetGid.eq(target_gid))
+                .one(&app_state.db)
+                .await?
+                .and_then(|ri| ri.mid);
             let active_model = ActiveModel {
                 id: Default::default(),
                 uid: Set(uid),
@@ -107,6 +129,7 @@ pub(crate) async fn set_read_index(
                 mid: Set(Some(mid)),
                 latest_mid: Set(mid),
                 uid_of_latest_msg: Set(uid),
+                version: Set(version),
             };
             read_index::Entity::insert(active_model)
                 .on_conflict(
@@ -118,6 +141,7 @@ pub(crate) async fn set_read_index(
                         read_index::Column::Mid,
                         read_index::Column::LatestMid,
                         read_index::Column::UidOfLatestMsg,
+                        read_index::Column::Version,
                     ])
                     .to_owned(),
                 )
@@ -135,6 +159,7 @@ pub(crate) async fn set_read_index(
                         mid: Set(None),
                         latest_mid: Set(mid),
                         uid_of_latest_msg: Set(uid),
+                        version: Set(version),
                     };
                 })
                 .collect::<Vec<ActiveModel>>();
@@ -147,15 +172,134 @@ pub(crate) async fn set_read_index(
                     .update_columns(vec![
                         read_index::Column::LatestMid,
                         read_index::Column::UidOfLatestMsg,
+                        read_index::Column::Version,
                     ])
                     .to_owned(),
                 )
                 .exec(&app_state.db)
                 .await?;
+            message::read_group(uid, target_gid, read_mid, mid, app_state).await?;
         }
     })
 }
 
+/// 生成已读位置的版本号，每次修改read_index时更新，增量同步据此查询有变化的已读位置
+fn next_version(app_state: &AppState) -> Result<i64, ServerError> {
+    Ok(app_state.msg_db.lock().unwrap().messages().generate_id()?)
+}
+
+/// 会话的已读位置
+#[derive(Serialize, Debug)]
+pub struct ReadIndexVo {
+    pub target: MessageTarget,
+    /// 已读到的消息id
+    pub mid: Option<i64>,
+    /// 会话中最新的消息id
+    pub latest_mid: i64,
+    /// 最新消息的发送者id
+    pub uid_of_latest_msg: i32,
+    /// 已读位置的版本号
+    pub version: i64,
+}
+
+/// 查询用户版本号在after之后有变化的会话已读位置，after为空时返回全部
+pub(crate) async fn get_changed_after(
+    uid: i32,
+    after: Option<i64>,
+    app_state: &AppState,
+) -> Result<Vec<ReadIndexVo>, DbErr> {
+    let mut select = read_index::Entity::find().filter(read_index::Column::Uid.eq(uid));
+    if let Some(after) = after {
+        select = select.filter(read_index::Column::Version.gt(after));
+    }
+    Ok(select
+        .all(&app_state.db)
+        .await?
+        .into_iter()
+        .filter_map(|ri| {
+            let target = match (ri.target_uid, ri.target_gid) {
+                (Some(uid), None) => MessageTarget::User(MessageTargetUser { uid }),
+                (None, Some(gid)) => MessageTarget::Group(MessageTargetGroup { gid }),
+                _ => return None,
+            };
+            Some(ReadIndexVo {
+                target,
+                mid: ri.mid,
+                latest_mid: ri.latest_mid,
+                uid_of_latest_msg: ri.uid_of_latest_msg,
+                version: ri.version,
+            })
+        })
+        .collect())
+}
+
+/// 查询群成员的已读位置，未读过群消息的成员不在结果中
+pub(crate) async fn get_group_read_mids(
+    gid: i32,
+    app_state: &AppState,
+) -> Result<HashMap<i32, i64>, DbErr> {
+    Ok(read_index::Entity::find()
+        .filter(read_index::Column::TargetGid.eq(gid))
+        .all(&app_state.db)
+        .await?
+        .into_iter()
+        .filter_map(|ri| Some((ri.uid, ri.mid?)))
+        .collect())
+}
+
+/// 会话中的消息被删除后，将指向已删除消息的latest_mid改为会话中最新的消息（消息id与发送者id），
+/// 会话中已没有消息时删除对应的read_index
+pub(crate) async fn reset_latest_mid(
+    conversation: Conversation,
+    removed: Vec<i64>,
+    latest: Option<(i64, i32)>,
+    app_state: &AppState,
+) -> Result<(), ServerError> {
+    let target = match conversation {
+        Conversation::Dm(a, b) => {
+            let (a, b) = (a as i32, b as i32);
+            Condition::any()
+                .add(
+                    read_index::Column::Uid
+                        .eq(a)
+                        .and(read_index::Column::TargetUid.eq(b)),
+                )
+                .add(
+                    read_index::Column::Uid
+                        .eq(b)
+                        .and(read_index::Column::TargetUid.eq(a)),
+                )
+        }
+        Conversation::Group(gid) => {
+            Condition::all().add(read_index::Column::TargetGid.eq(gid as i32))
+        }
+    };
+    let condition = Condition::all()
+        .add(target)
+        .add(read_index::Column::LatestMid.is_in(removed));
+    match latest {
+        Some((mid, uid)) => {
+            read_index::Entity::update_many()
+                .col_expr(read_index::Column::LatestMid, Expr::value(mid))
+                .col_expr(read_index::Column::UidOfLatestMsg, Expr::value(uid))
+                .col_expr(
+                    read_index::Column::Version,
+                    Expr::value(next_version(app_state)?),
+                )
+                .filter(condition)
+                .exec(&app_state.db)
+                .await?;
+        }
+        None => {
+            read_index::Entity::delete_many()
+                .filter(condition)
+                .exec(&app_state.db)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
 pub(crate) fn count_unread_msg(ri: &Model, app_state: &AppState) -> Option<String> {
     match (ri.target_uid, ri.target_gid) {
         (Some(target_uid), None) => {
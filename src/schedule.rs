@@ -0,0 +1,307 @@
+use std::env;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use axum::extract::{Path, State};
+use axum::routing::{get, patch};
+use axum::{Json, Router};
+use chrono::{DateTime, Local, TimeZone};
+use msg::Schedule;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::warn;
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::app_state::AppState;
+use crate::auth::Token;
+use crate::datetime::{datetime_format, opt_datetime_format};
+use crate::err::{ErrPrint, ServerError};
+use crate::message::{MessageTarget, SendMsgReq};
+use crate::user::UserErr;
+use crate::validate::ValidatedJson;
+use crate::{message, middleware, user, Api, Res};
+
+/// 定时消息最晚的发送时间，默认30天后
+static MAX_DELAY: LazyLock<Duration> = LazyLock::new(|| {
+    Duration::from_secs(
+        env::var("SCHEDULE_MAX_DELAY_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(30 * 24 * 3600),
+    )
+});
+
+/// 每个用户待发送的定时消息上限
+const MAX_SCHEDULES: usize = 100;
+
+/// 调度器检查到期消息的间隔
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 调度器每次最多取出的到期消息数量
+const DISPATCH_BATCH: usize = 100;
+
+pub struct ScheduleApi;
+
+impl Api for ScheduleApi {
+    fn route(app_state: AppState) -> Router {
+        Router::new()
+            .route("/", get(list).post(create))
+            .route("/:id", patch(edit).delete(cancel))
+            .route_layer(axum::middleware::from_fn_with_state(
+                app_state.clone(),
+                middleware::check_user_status,
+            ))
+            .route_layer(axum::middleware::from_fn_with_state(
+                app_state.clone(),
+                middleware::check_login,
+            ))
+            .with_state(app_state.clone())
+    }
+}
+
+/// 定时消息相关错误
+#[derive(Debug, Error, ToSchema)]
+pub enum ScheduleErr {
+    /// 定时消息不存在或已发送
+    #[error("定时消息{0}不存在或已发送")]
+    ScheduleNotExist(i64),
+    /// 发送时间不合法
+    #[error("发送时间需在当前时间之后的{0}秒内")]
+    InvalidSendAt(u64),
+    /// 待发送的定时消息过多
+    #[error("待发送的定时消息最多{0}条")]
+    TooManySchedules(usize),
+}
+
+impl ErrPrint for ScheduleErr {}
+
+/// 持久化的定时消息内容
+#[derive(Serialize, Deserialize)]
+struct ScheduledMsg {
+    target: MessageTarget,
+    msg: SendMsgReq,
+}
+
+/// 添加定时消息请求
+#[derive(Deserialize, Validate, Debug)]
+pub struct ScheduleReq {
+    /// 发送目标
+    pub target: MessageTarget,
+    /// 发送时间
+    #[serde(with = "datetime_format")]
+    pub send_at: DateTime<Local>,
+    #[validate(nested)]
+    pub msg: SendMsgReq,
+}
+
+/// 修改定时消息请求，未指定的字段保持不变
+#[derive(Deserialize, Validate, Debug)]
+pub struct EditScheduleReq {
+    #[serde(default, with = "opt_datetime_format")]
+    pub send_at: Option<DateTime<Local>>,
+    #[validate(nested)]
+    pub msg: Option<SendMsgReq>,
+}
+
+/// 待发送的定时消息
+#[derive(Serialize)]
+struct ScheduleVo {
+    id: i64,
+    target: MessageTarget,
+    #[serde(with = "datetime_format")]
+    send_at: DateTime<Local>,
+    msg: SendMsgReq,
+}
+
+impl TryFrom<Schedule> for ScheduleVo {
+    type Error = ServerError;
+
+    fn try_from(schedule: Schedule) -> Result<Self, Self::Error> {
+        let ScheduledMsg { target, msg } = decode(&schedule)?;
+        Ok(ScheduleVo {
+            id: schedule.id,
+            target,
+            send_at: Local
+                .timestamp_millis_opt(schedule.send_at)
+                .single()
+                .unwrap_or_default(),
+            msg,
+        })
+    }
+}
+
+fn encode(scheduled: &ScheduledMsg) -> Result<Vec<u8>, ServerError> {
+    serde_json::to_vec(scheduled)
+        .map_err(|_| ServerError::CustomErr("fail to serialize scheduled msg".to_string()))
+}
+
+fn decode(schedule: &Schedule) -> Result<ScheduledMsg, ServerError> {
+    serde_json::from_slice(&schedule.data)
+        .map_err(|_| ServerError::CustomErr("fail to deserialize scheduled msg".to_string()))
+}
+
+fn check_send_at(send_at: DateTime<Local>) -> Result<(), ScheduleErr> {
+    let now = Local::now();
+    if send_at <= now || send_at > now + *MAX_DELAY {
+        return Err(ScheduleErr::InvalidSendAt(MAX_DELAY.as_secs()));
+    }
+    Ok(())
+}
+
+/// 查询用户的定时消息，不存在或不属于该用户时返回ScheduleNotExist
+fn get_own(id: i64, token: &Token, app_state: &AppState) -> Result<Schedule, ServerError> {
+    app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .get_schedule(id)?
+        .filter(|schedule| schedule.uid == token.id as i64)
+        .ok_or(ScheduleErr::ScheduleNotExist(id).into())
+}
+
+/// 添加定时消息，到期后以当前用户的身份发送，返回定时消息id
+async fn create(
+    State(app_state): State<AppState>,
+    token: Token,
+    ValidatedJson(req): ValidatedJson<ScheduleReq>,
+) -> Res<String> {
+    check_send_at(req.send_at)?;
+    message::check_can_send(req.target, &app_state, &token).await?;
+    let data = encode(&ScheduledMsg {
+        target: req.target,
+        msg: req.msg,
+    })?;
+    let msg_db = app_state.msg_db.lock().unwrap();
+    if msg_db
+        .messages()
+        .fetch_user_schedules(token.id as i64)?
+        .len()
+        >= MAX_SCHEDULES
+    {
+        return Err(ScheduleErr::TooManySchedules(MAX_SCHEDULES).into());
+    }
+    let id = msg_db.messages().insert_schedule(
+        token.id as i64,
+        req.send_at.timestamp_millis(),
+        &data,
+    )?;
+    Ok(id.to_string())
+}
+
+/// 查询当前用户待发送的定时消息，按发送时间升序返回
+async fn list(State(app_state): State<AppState>, token: Token) -> Res<Json<Vec<ScheduleVo>>> {
+    let schedules = app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .fetch_user_schedules(token.id as i64)?;
+    let mut schedules = schedules
+        .into_iter()
+        .map(ScheduleVo::try_from)
+        .collect::<Result<Vec<ScheduleVo>, ServerError>>()?;
+    schedules.sort_by_key(|schedule| (schedule.send_at, schedule.id));
+    Ok(Json(schedules))
+}
+
+/// 修改待发送的定时消息的发送时间或内容
+async fn edit(
+    State(app_state): State<AppState>,
+    Path(id): Path<i64>,
+    token: Token,
+    ValidatedJson(req): ValidatedJson<EditScheduleReq>,
+) -> Res<()> {
+    let schedule = get_own(id, &token, &app_state)?;
+    let mut scheduled = decode(&schedule)?;
+    let send_at = match req.send_at {
+        Some(send_at) => {
+            check_send_at(send_at)?;
+            send_at.timestamp_millis()
+        }
+        None => schedule.send_at,
+    };
+    if let Some(msg) = req.msg {
+        scheduled.msg = msg;
+    }
+    message::check_can_send(scheduled.target, &app_state, &token).await?;
+    let updated = app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .update_schedule(&schedule, send_at, &encode(&scheduled)?)?;
+    if !updated {
+        return Err(ScheduleErr::ScheduleNotExist(id).into());
+    }
+    Ok(())
+}
+
+/// 取消待发送的定时消息
+async fn cancel(State(app_state): State<AppState>, Path(id): Path<i64>, token: Token) -> Res<()> {
+    get_own(id, &token, &app_state)?;
+    app_state
+        .msg_db
+        .lock()
+        .unwrap()
+        .messages()
+        .remove_schedule(id)?
+        .ok_or(ScheduleErr::ScheduleNotExist(id))?;
+    Ok(())
+}
+
+/// 后台调度定时消息，到期后以发送者的身份发送。添加与修改时已校验过目标，
+/// 发送时仍需重新校验发送者的状态与权限，期间可能已被删除好友或移出群
+pub async fn dispatch(app_state: AppState) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        let due = app_state
+            .msg_db
+            .lock()
+            .unwrap()
+            .messages()
+            .fetch_due_schedules(Local::now().timestamp_millis(), DISPATCH_BATCH);
+        let due = match due {
+            Ok(due) => due,
+            Err(err) => {
+                warn!("fail to fetch due scheduled messages: {err}");
+                continue;
+            }
+        };
+        for schedule in due {
+            // 先从队列中取出再发送，与修改、取消并发时只有一方生效
+            let schedule = match app_state
+                .msg_db
+                .lock()
+                .unwrap()
+                .messages()
+                .remove_schedule(schedule.id)
+            {
+                Ok(Some(schedule)) => schedule,
+                Ok(None) => continue,
+                Err(err) => {
+                    warn!("fail to take scheduled message {}: {err}", schedule.id);
+                    continue;
+                }
+            };
+            let (id, uid) = (schedule.id, schedule.uid);
+            if let Err(err) = send(schedule, &app_state).await {
+                warn!("fail to send scheduled message {id} of user {uid}: {err}");
+            }
+        }
+    }
+}
+
+async fn send(schedule: Schedule, app_state: &AppState) -> Result<i64, ServerError> {
+    let uid = schedule.uid as i32;
+    user::check_status(uid, uid, app_state).await?;
+    let token = Token::from(
+        user::get_by_id(uid, app_state)
+            .await?
+            .ok_or(UserErr::UserNotExist(uid))?,
+    );
+    let ScheduledMsg { target, msg } = decode(&schedule)?;
+    message::send_to(target, msg.into_detail(), app_state, &token).await
+}
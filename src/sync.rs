@@ -0,0 +1,92 @@
+use axum::extract::{Query, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::app_state::AppState;
+use crate::auth::Token;
+use crate::group::Membership;
+use crate::message::ChatMessage;
+use crate::read_index::ReadIndexVo;
+use crate::{group, message, middleware, read_index, Api, Res};
+
+pub struct SyncApi;
+
+impl Api for SyncApi {
+    fn route(app_state: AppState) -> Router {
+        Router::new()
+            .route("/", get(sync))
+            .route_layer(axum::middleware::from_fn_with_state(
+                app_state.clone(),
+                middleware::check_login,
+            ))
+            .with_state(app_state.clone())
+    }
+}
+
+/// 增量同步默认每页消息数量
+const DEFAULT_SYNC_LIMIT: usize = 100;
+
+/// 增量同步参数
+#[derive(Deserialize, Validate, Debug)]
+pub struct SyncQuery {
+    /// 客户端最后同步到的消息id，不指定时从第一条消息开始同步
+    pub after: Option<i64>,
+    /// 客户端最后同步到的已读位置版本号，分页同步时每页传入同一值，不指定时返回全部已读位置
+    pub read_after: Option<i64>,
+    /// 每页消息数量，默认100，最大500
+    #[validate(range(min = 1, max = 500))]
+    pub limit: Option<usize>,
+}
+
+/// 一页增量同步结果
+#[derive(Serialize, Debug)]
+pub struct SyncPage {
+    /// after之后用户收到的消息，按消息id升序排列，不含用户隐藏或已清空的消息
+    pub messages: Vec<ChatMessage>,
+    /// 下一页的after，同步完成后客户端保存为最后同步到的消息id
+    pub next: Option<i64>,
+    pub has_more: bool,
+    /// read_after之后有变化的已读位置，仅在最后一页返回
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_indexes: Option<Vec<ReadIndexVo>>,
+    /// 下次同步的read_after，仅在最后一页返回，客户端与next一同保存
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_next: Option<i64>,
+    /// 当前加入的所有群，客户端据此比对入群与退群，仅在最后一页返回
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub groups: Option<Vec<Membership>>,
+}
+
+/// 多端增量同步，分页返回after之后的消息，最后一页附带已读位置与群成员关系的变化
+async fn sync(
+    State(app_state): State<AppState>,
+    token: Token,
+    Query(query): Query<SyncQuery>,
+) -> Res<Json<SyncPage>> {
+    query.validate()?;
+    let limit = query.limit.unwrap_or(DEFAULT_SYNC_LIMIT);
+    let (messages, last_mid, has_more) =
+        message::get_user_visible_msg_after(&app_state, token.id, query.after, limit)?;
+    let mut page = SyncPage {
+        messages,
+        next: last_mid.or(query.after),
+        has_more,
+        read_indexes: None,
+        read_next: None,
+        groups: None,
+    };
+    if !has_more {
+        let read_indexes =
+            read_index::get_changed_after(token.id, query.read_after, &app_state).await?;
+        page.read_next = read_indexes
+            .iter()
+            .map(|ri| ri.version)
+            .max()
+            .max(query.read_after);
+        page.read_indexes = Some(read_indexes);
+        page.groups = Some(group::get_memberships(token.id, &app_state).await?);
+    }
+    Ok(Json(page))
+}
@@ -0,0 +1,105 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::app_state::AppState;
+use crate::auth::Token;
+use crate::err::ServerError;
+use crate::event::BroadcastEvent;
+use crate::friend::FriendErr;
+use crate::group::GroupErr;
+use crate::message::{MessageTarget, MessageTargetGroup, MessageTargetUser};
+use crate::{friend, group};
+
+/// 同一用户在同一会话推送正在输入状态的最小间隔，间隔内的重复信号被丢弃
+const TYPING_INTERVAL: Duration = Duration::from_secs(2);
+
+/// 正在输入状态的有效时长，客户端在此时间内未再收到信号即视为停止输入
+const TYPING_EXPIRE: Duration = Duration::from_secs(5);
+
+/// 推送正在输入状态的群成员数上限，超过时不推送
+const TYPING_MAX_MEMBERS: usize = 50;
+
+/// 正在输入状态的推送节流，记录用户在各会话最近一次推送的时间
+#[derive(Default)]
+pub struct TypingThrottle {
+    typing_at: Mutex<HashMap<(i32, MessageTarget), Instant>>,
+}
+
+impl TypingThrottle {
+    /// 用户在会话中是否刚推送过正在输入状态，record为true时记录本次推送
+    fn throttled(&self, uid: i32, target: MessageTarget, record: bool) -> bool {
+        let now = Instant::now();
+        let mut typing_at = self.typing_at.lock().unwrap();
+        if typing_at
+            .get(&(uid, target))
+            .is_some_and(|&at| now.duration_since(at) < TYPING_INTERVAL)
+        {
+            return true;
+        }
+        if record {
+            typing_at.retain(|_, &mut at| now.duration_since(at) < TYPING_INTERVAL);
+            typing_at.insert((uid, target), now);
+        }
+        false
+    }
+}
+
+/// 推送正在输入状态，不持久化，只推送给单聊的对方或群内的其他成员
+pub(crate) async fn send(
+    target: MessageTarget,
+    app_state: &AppState,
+    token: &Token,
+) -> Result<(), ServerError> {
+    if app_state.typing.throttled(token.id, target, false) {
+        return Ok(());
+    }
+    let targets = match target {
+        MessageTarget::User(MessageTargetUser { uid }) => {
+            if !friend::is_friend(token.dgraph_uid.clone(), uid).await {
+                return Err(FriendErr::NotFriend(uid).into());
+            }
+            BTreeSet::from([uid])
+        }
+        MessageTarget::Group(MessageTargetGroup { gid }) => {
+            let uids = group::get_uids(app_state, gid).await?;
+            if !uids.contains(&token.id) {
+                return Err(GroupErr::UserNotInGroup { uid: token.id, gid }.into());
+            }
+            if uids.len() > TYPING_MAX_MEMBERS {
+                return Ok(());
+            }
+            uids.into_iter().filter(|&uid| uid != token.id).collect()
+        }
+    };
+    // 校验通过后才占用推送间隔，并发的重复信号只推送一次
+    if app_state.typing.throttled(token.id, target, true) {
+        return Ok(());
+    }
+    app_state.hub.send(BroadcastEvent::Typing {
+        targets,
+        target,
+        uid: token.id,
+        expires_in: TYPING_EXPIRE.as_secs(),
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::message::{MessageTarget, MessageTargetGroup, MessageTargetUser};
+    use crate::typing::TypingThrottle;
+
+    #[test]
+    fn throttled_per_conversation() {
+        let throttle = TypingThrottle::default();
+        let dm = MessageTarget::User(MessageTargetUser { uid: 2 });
+        let group = MessageTarget::Group(MessageTargetGroup { gid: 1 });
+        assert!(!throttle.throttled(1, dm, false));
+        assert!(!throttle.throttled(1, dm, false));
+        assert!(!throttle.throttled(1, dm, true));
+        assert!(throttle.throttled(1, dm, true));
+        assert!(!throttle.throttled(1, group, true));
+        assert!(!throttle.throttled(2, dm, true));
+    }
+}
@@ -1,8 +1,8 @@
 use std::collections::HashMap;
 use std::option::Option;
 
-use axum::extract::{Path, State};
-use axum::routing::{get, patch, post};
+use axum::extract::{Path, Query, State};
+use axum::routing::{delete, get, patch, post, put};
 use axum::{Json, Router};
 use chrono::{DateTime, Local};
 use itertools::Itertools;
@@ -21,16 +21,18 @@ use crate::datetime::opt_datetime_format;
 use crate::err::{ErrPrint, ServerError};
 use crate::friend::{FriendErr, FriendRegister};
 use crate::message::{
-    ChatMessage, HistoryMsgReq, HistoryMsgUser, HistoryReq, MessageTarget, MessageTargetUser,
-    SendMsgReq,
+    ChatMessage, ChatMessagePayload, ContentBody, HistoryMsgReq, HistoryMsgUser, HistoryPage,
+    HistoryQuery, MessageDetail, MessageTarget, MessageTargetUser, PinnedMsg, Quote,
+    ReactionSummary, SendMsgReq, Ttl,
 };
 use crate::read_index::UpdateReadIndex;
 use crate::validate::ValidatedJson;
-use crate::{auth, datetime, friend, group, message, middleware, Res};
-use crate::{read_index, Api};
+use crate::{auth, datetime, friend, group, message, middleware, presence, Res};
+use crate::{read_index, typing, Api};
 use entity::prelude::User;
 use entity::sea_orm_active_enums::UserStatus;
 use entity::user;
+use msg::Conversation;
 
 #[derive(OpenApi)]
 #[openapi(
@@ -38,8 +40,11 @@ use entity::user;
         register,send,user_history,password,detail,history
     ),
     components(
-        schemas(UserRegisterReq,SendMsgReq,UserHistoryMsg,PasswordReq,
-        UserDetail,ChatVo,UserErr,friend::FriendErr)
+        schemas(UserRegisterReq,SendMsgReq,UserHistoryMsg,message::UserHistoryPage,PasswordReq,
+        UserDetail,ChatVo,UserErr,friend::FriendErr,Quote,ReactionSummary,ContentBody,
+        message::ImageContent,message::Thumbnail,message::FileContent,message::AudioContent,
+        message::LocationContent,message::CardContent,message::StickerContent,
+        message::Mentions)
     ),
     tags(
         (name = "user", description = "USER API")
@@ -52,12 +57,18 @@ impl Api for UserApi {
         Router::new()
             .route("/:uid/send", post(send))
             .route("/password", patch(password))
+            .route("/last_seen", patch(last_seen))
             .route("/:name", get(detail))
+            .route("/:uid/ttl", put(set_ttl))
+            .route("/:uid/typing", post(typing))
+            .route("/:uid/history", delete(clear_history))
             .route_layer(axum::middleware::from_fn_with_state(
                 app_state.clone(),
                 middleware::check_user_status,
             ))
             .route("/:uid/history", get(user_history))
+            .route("/:uid/pins", get(pins))
+            .route("/:uid/ttl", get(ttl))
             .route("/history/:limit", get(history))
             .route("/find/:name", get(find_friend))
             .route_layer(axum::middleware::from_fn_with_state(
@@ -164,6 +175,8 @@ async fn register(
         status: Default::default(),
         dgraph_uid: Default::default(),
         role: Default::default(),
+        last_seen: Default::default(),
+        hide_last_seen: Default::default(),
     };
     let user = user.insert(&app_state.db).await?;
     // save dgraph, get dgraph_uid
@@ -244,17 +257,43 @@ async fn send(
     // 按照参数定义的先后顺序进行解析，ValidatedJson会消耗掉Request，因此要放在最后面解析
     ValidatedJson(msg): ValidatedJson<SendMsgReq>,
 ) -> Res<String> {
+    let target = MessageTarget::User(MessageTargetUser { uid });
+    let mid = message::send_once(target, msg, &app_state, &token).await?;
+    Ok(mid.to_string())
+}
+
+/// 校验好友状态正常且双方是好友
+pub(crate) async fn check_can_send(
+    uid: i32,
+    token: &Token,
+    app_state: &AppState,
+) -> Result<(), ServerError> {
     // 校验好友状态
-    check_status(uid, token.id, &app_state).await?;
+    check_status(uid, token.id, app_state).await?;
     // 判断是否是好友
-    if !friend::is_friend(token.dgraph_uid, uid).await {
+    if !friend::is_friend(token.dgraph_uid.clone(), uid).await {
         return Err(FriendErr::NotFriend(uid).into());
     }
-    let payload = msg.build_payload(token.id, MessageTarget::User(MessageTargetUser { uid }));
-    let mid = message::send_msg(payload, &app_state).await?;
+    Ok(())
+}
+
+/// 向好友发送消息，供http接口与websocket共用
+pub(crate) async fn send_to_friend(
+    app_state: &AppState,
+    token: &Token,
+    uid: i32,
+    detail: MessageDetail,
+) -> Result<i64, ServerError> {
+    check_can_send(uid, token, app_state).await?;
+    let payload = ChatMessagePayload::new(
+        token.id,
+        MessageTarget::User(MessageTargetUser { uid }),
+        detail,
+    );
+    let mid = message::send_msg(payload, app_state).await?;
     // 设置read_index
     read_index::set_read_index(
-        &app_state,
+        app_state,
         token.id,
         UpdateReadIndex::User {
             target_uid: uid,
@@ -262,12 +301,12 @@ async fn send(
         },
     )
     .await?;
-    Ok(mid.to_string())
+    Ok(mid)
 }
 
 /// 历史聊天记录
 #[derive(Serialize, ToSchema)]
-struct UserHistoryMsg {
+pub struct UserHistoryMsg {
     /// 消息id
     mid: i64,
     /// 消息内容
@@ -277,16 +316,28 @@ struct UserHistoryMsg {
     time: DateTime<Local>,
     /// 消息发送者id
     from_uid: i32,
+    /// 是否编辑过
+    edited: bool,
+    /// 非文本消息的内容
+    body: Option<ContentBody>,
+    /// 引用的消息
+    quote: Option<Quote>,
+    /// 表情回应
+    reactions: Vec<ReactionSummary>,
 }
 
 #[utoipa::path(
     get,
     path = "/{uid}/history",
     params(
-        ("uid" = i32, Path, description = "id of friend")
+        ("uid" = i32, Path, description = "id of friend"),
+        ("before" = Option<i64>, Query, description = "messages before this mid"),
+        ("after" = Option<i64>, Query, description = "messages after this mid"),
+        ("around" = Option<i64>, Query, description = "this mid and messages around it"),
+        ("limit" = Option<usize>, Query, description = "page size, 50 by default, 200 at most")
     ),
     responses(
-        (status = 200, description = "Get history message successfully", body = [UserHistoryMsg]),
+        (status = 200, description = "Get a page of history message successfully", body = UserHistoryPage),
         (status = 401, description = "Target user is not friend of you", body = FriendErr),
     ),
 )]
@@ -295,33 +346,98 @@ async fn user_history(
     State(app_state): State<AppState>,
     Path(uid): Path<i32>,
     token: Token,
-) -> Res<Json<Vec<UserHistoryMsg>>> {
+    Query(query): Query<HistoryQuery>,
+) -> Res<Json<HistoryPage<UserHistoryMsg>>> {
+    query.validate()?;
     if !friend::is_friend(token.dgraph_uid, uid).await {
         return Err(FriendErr::NotFriend(uid).into());
     }
-    let mut history_msg = message::get_history_msg(
+    let history_msg = message::get_history_msg(
         &app_state,
+        token.id,
         HistoryMsgReq::User(HistoryMsgUser {
             from_id: token.id,
             to_id: uid,
-            history: HistoryReq {
-                before: None,
-                limit: 1000,
-            },
+            history: query.into(),
         }),
-    );
-    Ok(Json(
-        history_msg
-            .into_iter()
-            .map(|x| UserHistoryMsg {
-                mid: x.mid,
-                msg: x.payload.detail.get_content(),
-                time: x.payload.created_at,
-                from_uid: x.payload.from_uid,
-            })
-            .sorted_by(|x1, x2| x1.time.cmp(&x2.time))
-            .collect(),
-    ))
+    )?;
+    let mut reactions = message::get_reactions(
+        history_msg.messages.iter().map(|x| x.mid),
+        token.id,
+        &app_state,
+    )?;
+    Ok(Json(history_msg.map(|x| UserHistoryMsg {
+        mid: x.mid,
+        msg: x.payload.detail.get_content(),
+        time: x.payload.created_at,
+        from_uid: x.payload.from_uid,
+        edited: x.payload.edited_at.is_some(),
+        body: x.payload.detail.get_body(),
+        quote: x.quote,
+        reactions: reactions.remove(&x.mid).unwrap_or_default(),
+    })))
+}
+
+/// 清空与好友的会话，只对自己生效
+async fn clear_history(
+    State(app_state): State<AppState>,
+    Path(uid): Path<i32>,
+    token: Token,
+) -> Res<()> {
+    if !friend::is_friend(token.dgraph_uid, uid).await {
+        return Err(FriendErr::NotFriend(uid).into());
+    }
+    let conversation = Conversation::dm(token.id as i64, uid as i64);
+    message::clear_conversation(token.id, conversation, &app_state)?;
+    Ok(())
+}
+
+/// 查询与好友的会话中置顶的消息
+async fn pins(
+    State(app_state): State<AppState>,
+    Path(uid): Path<i32>,
+    token: Token,
+) -> Res<Json<Vec<PinnedMsg>>> {
+    if !friend::is_friend(token.dgraph_uid, uid).await {
+        return Err(FriendErr::NotFriend(uid).into());
+    }
+    let pins = message::get_pins(Conversation::dm(token.id as i64, uid as i64), &app_state)?;
+    Ok(Json(pins))
+}
+
+/// 查询与好友的会话的消息保留时长
+async fn ttl(
+    State(app_state): State<AppState>,
+    Path(uid): Path<i32>,
+    token: Token,
+) -> Res<Json<Ttl>> {
+    if !friend::is_friend(token.dgraph_uid, uid).await {
+        return Err(FriendErr::NotFriend(uid).into());
+    }
+    let ttl = message::get_ttl(Conversation::dm(token.id as i64, uid as i64), &app_state)?;
+    Ok(Json(ttl))
+}
+
+/// 设置与好友的会话的消息保留时长，双方都可以设置
+async fn set_ttl(
+    State(app_state): State<AppState>,
+    Path(uid): Path<i32>,
+    token: Token,
+    ValidatedJson(ttl): ValidatedJson<Ttl>,
+) -> Res<()> {
+    if !friend::is_friend(token.dgraph_uid.clone(), uid).await {
+        return Err(FriendErr::NotFriend(uid).into());
+    }
+    let target = MessageTarget::User(MessageTargetUser { uid });
+    message::set_ttl(target, ttl, &app_state, &token).await?;
+    Ok(())
+}
+
+/// 通知好友当前用户正在输入
+async fn typing(State(app_state): State<AppState>, Path(uid): Path<i32>, token: Token) -> Res<()> {
+    let target = MessageTarget::User(MessageTargetUser { uid });
+    typing::send(target, &app_state, &token).await?;
+    Ok(())
 }
 
 #[derive(Hash, Clone, PartialEq, Eq)]
@@ -368,6 +484,8 @@ enum ChatVo {
         msg_time: DateTime<Local>,
         /// unread message count
         unread: Option<String>,
+        /// whether you are mentioned in unread messages
+        mentioned: bool,
     },
 }
 
@@ -401,7 +519,27 @@ async fn history(
         .limit(limit)
         .all(&app_state.db)
         .await?;
-    let map = ris
+    // 最新消息被隐藏或清空时回退到可见的最新消息，没有可见消息的会话不展示
+    let mut visible_ris = Vec::with_capacity(ris.len());
+    for mut ri in ris {
+        let conversation = match (ri.target_uid, ri.target_gid) {
+            (Some(target_uid), None) => Conversation::dm(token.id as i64, target_uid as i64),
+            (None, Some(target_gid)) => Conversation::Group(target_gid as i64),
+            _ => continue,
+        };
+        // 清空位置之前的消息不计入未读
+        let cleared = message::get_cleared(token.id, conversation, &app_state)?;
+        ri.mid = ri.mid.max(cleared);
+        let Some(latest) =
+            message::get_latest_visible(token.id, conversation, ri.latest_mid, &app_state)?
+        else {
+            continue;
+        };
+        ri.latest_mid = latest.mid;
+        ri.uid_of_latest_msg = latest.payload.from_uid;
+        visible_ris.push(ri);
+    }
+    let map = visible_ris
         .into_iter()
         .filter_map(|x| match (x.target_uid, x.target_gid) {
             (Some(_), None) => Some((ChatTarget::User, x)),
@@ -480,6 +618,12 @@ async fn history(
                 .into_iter()
                 .map(|x| (x.id, x.name))
                 .collect::<HashMap<i32, String>>();
+            let mut gid_2_mentioned = HashMap::new();
+            for x in ris_of_group {
+                let gid = x.target_gid.unwrap();
+                let mentioned = message::has_unread_mention(token.id, gid, x.mid, &app_state)?;
+                gid_2_mentioned.insert(gid, mentioned);
+            }
             ris_of_group
                 .into_iter()
                 .map(|x| ChatVo::Group {
@@ -503,6 +647,10 @@ async fn history(
                         .map(|x| x.payload.created_at)
                         .unwrap_or(Local::now()),
                     unread: read_index::count_unread_msg(x, &app_state),
+                    mentioned: gid_2_mentioned
+                        .get(&x.target_gid.unwrap())
+                        .copied()
+                        .unwrap_or_default(),
                 })
                 .collect()
         }
@@ -544,6 +692,23 @@ pub async fn get_by_id(uid: i32, app_state: &AppState) -> Result<Option<user::Mo
         .await
 }
 
+/// 设置最后在线时间的可见性
+#[derive(Deserialize, ToSchema)]
+struct LastSeenReq {
+    /// 为true时好友看不到当前用户的最后在线时间
+    hidden: bool,
+}
+
+/// 设置是否对好友隐藏最后在线时间
+async fn last_seen(
+    State(app_state): State<AppState>,
+    token: Token,
+    Json(req): Json<LastSeenReq>,
+) -> Res<()> {
+    presence::hide_last_seen(token.id, req.hidden, &app_state).await?;
+    Ok(())
+}
+
 /// 修改密码
 #[derive(Deserialize, ToSchema, Validate)]
 struct PasswordReq {